@@ -0,0 +1,202 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub const DECIMALS: u32 = 8;
+pub const UNITS_PER_COIN: u64 = 100_000_000;
+
+// A non-negative amount of currency, counted in indivisible base units of
+// 10^-8 coins. Arithmetic is checked, so overflows surface as None instead
+// of wrapping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub fn from_units(units: u64) -> Amount {
+        Amount(units)
+    }
+
+    pub fn from_coins(coins: u64) -> Option<Amount> {
+        coins.checked_mul(UNITS_PER_COIN).map(Amount)
+    }
+
+    pub fn units(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    pub fn checked_sum<I: IntoIterator<Item = Amount>>(amounts: I) -> Option<Amount> {
+        amounts
+            .into_iter()
+            .try_fold(Amount::ZERO, |sum, amount| sum.checked_add(amount))
+    }
+
+    // Amounts were floating point coins before base units, transactions of
+    // encoding versions 0 and 1 are still hashed that way.
+    pub fn to_legacy(self) -> f64 {
+        self.0 as f64 / UNITS_PER_COIN as f64
+    }
+
+    // Only accepts coins that convert back to exactly the same float, so the
+    // legacy hashes of a loaded transaction don't change.
+    pub fn from_legacy(coins: f64) -> Option<Amount> {
+        let units = (coins * UNITS_PER_COIN as f64).round();
+        if !(0.0..u64::MAX as f64).contains(&units) {
+            return None;
+        }
+        let amount = Amount(units as u64);
+        (amount.to_legacy() == coins).then_some(amount)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let coins = self.0 / UNITS_PER_COIN;
+        let units = self.0 % UNITS_PER_COIN;
+        if units == 0 {
+            return write!(f, "{}", coins);
+        }
+        let fraction = format!("{:0width$}", units, width = DECIMALS as usize);
+        write!(f, "{}.{}", coins, fraction.trim_end_matches('0'))
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseAmountError {
+    Invalid(String),
+    TooManyDecimals(String),
+    Overflow(String),
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseAmountError::Invalid(input) => write!(f, "{:?} is not an amount", input),
+            ParseAmountError::TooManyDecimals(input) => {
+                write!(f, "{} has more than {} decimals", input, DECIMALS)
+            }
+            ParseAmountError::Overflow(input) => write!(f, "{} is too large", input),
+        }
+    }
+}
+
+impl std::error::Error for ParseAmountError {}
+
+// Parses a decimal number of coins such as "12" or "0.5".
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(input: &str) -> Result<Amount, ParseAmountError> {
+        let (coins, fraction) = input.split_once('.').unwrap_or((input, ""));
+        let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if !digits(coins) || (input.contains('.') && !digits(fraction)) {
+            return Err(ParseAmountError::Invalid(input.to_string()));
+        }
+        if fraction.len() > DECIMALS as usize {
+            return Err(ParseAmountError::TooManyDecimals(input.to_string()));
+        }
+
+        let overflow = || ParseAmountError::Overflow(input.to_string());
+        let coins: u64 = coins.parse().map_err(|_| overflow())?;
+        let units = format!("{:0<width$}", fraction, width = DECIMALS as usize)
+            .parse::<u64>()
+            .unwrap();
+        Amount::from_coins(coins)
+            .and_then(|amount| amount.checked_add(Amount(units)))
+            .ok_or_else(overflow)
+    }
+}
+
+// Serialized as the number of base units. A float is rejected instead of
+// being guessed at, `StoredAmount` reads the coins stored before base units.
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Amount, D::Error> {
+        match StoredAmount::deserialize(deserializer)? {
+            StoredAmount::Units(units) => Ok(Amount(units)),
+            StoredAmount::Coins(coins) => Err(de::Error::custom(format!(
+                "amount {} is not a number of base units",
+                coins
+            ))),
+        }
+    }
+}
+
+// An amount as stored by encoding version `version`. Data of versions 0 and 1
+// written before base units holds floating point coins, which serde_json
+// always writes with a decimal point, so they can't be mistaken for units.
+#[derive(Debug, Clone, Copy)]
+pub enum StoredAmount {
+    Units(u64),
+    Coins(f64),
+}
+
+impl Default for StoredAmount {
+    fn default() -> StoredAmount {
+        StoredAmount::Units(0)
+    }
+}
+
+impl StoredAmount {
+    pub fn into_amount(self, version: u32) -> Result<Amount, String> {
+        match self {
+            StoredAmount::Units(units) => Ok(Amount(units)),
+            StoredAmount::Coins(coins) if version > 1 => Err(format!(
+                "amount {} is not a number of base units, which version {} requires",
+                coins, version
+            )),
+            StoredAmount::Coins(coins) => Amount::from_legacy(coins)
+                .ok_or_else(|| format!("amount {} is not a whole number of base units", coins)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for StoredAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<StoredAmount, D::Error> {
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = StoredAmount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a number of base units")
+    }
+
+    fn visit_u64<E: de::Error>(self, units: u64) -> Result<StoredAmount, E> {
+        Ok(StoredAmount::Units(units))
+    }
+
+    fn visit_i64<E: de::Error>(self, units: i64) -> Result<StoredAmount, E> {
+        u64::try_from(units)
+            .map(StoredAmount::Units)
+            .map_err(|_| E::custom(format!("amount {} is negative", units)))
+    }
+
+    fn visit_f64<E: de::Error>(self, coins: f64) -> Result<StoredAmount, E> {
+        Ok(StoredAmount::Coins(coins))
+    }
+}
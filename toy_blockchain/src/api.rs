@@ -0,0 +1,230 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::blockchain::{Chain, Transaction};
+use crate::consensus::SealError;
+use crate::env::Logger;
+use crate::node::Node;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BODY: usize = 1 << 20;
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn ok<T: serde::Serialize>(body: &T) -> Response {
+        Response {
+            status: 200,
+            body: serde_json::to_string(body).unwrap(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response {
+            status,
+            body: json!({ "error": message }).to_string(),
+        }
+    }
+}
+
+// A small HTTP/1.1 server exposing the chain as JSON. Every connection
+// carries one request and is closed after the response.
+#[derive(Clone)]
+pub struct Api {
+    chain: Arc<Mutex<Chain>>,
+    node: Option<Node>,
+    logger: Arc<dyn Logger>,
+}
+
+impl Api {
+    pub fn new(chain: Arc<Mutex<Chain>>, node: Option<Node>) -> Api {
+        let logger = chain.lock().unwrap().logger();
+        Api {
+            chain,
+            node,
+            logger,
+        }
+    }
+
+    // Serves requests on `address` until the process exits.
+    pub fn serve(&self, address: &str) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        self.logger
+            .info(&format!("HTTP API listening on {}", address));
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let api = self.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = api.handle_connection(stream) {
+                            api.logger.error(&format!("HTTP connection error: {}", e));
+                        }
+                    });
+                }
+                Err(e) => self.logger.error(&format!("HTTP connection failed: {}", e)),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let response = match read_request(&stream)? {
+            Some(request) => self.route(&request),
+            None => Response::error(400, "malformed request"),
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.status,
+            reason(response.status),
+            response.body.len(),
+            response.body
+        )
+    }
+
+    fn route(&self, request: &Request) -> Response {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["chain"]) => Response::ok(&self.chain.lock().unwrap().chain),
+            ("GET", ["block", "hash", hash]) => {
+                let chain = self.chain.lock().unwrap();
+                match chain.chain.iter().find(|block| block.hash() == *hash) {
+                    Some(block) => Response::ok(block),
+                    None => Response::error(404, "block not found"),
+                }
+            }
+            ("GET", ["block", height]) => {
+                let chain = self.chain.lock().unwrap();
+                match height
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|h| chain.chain.get(h))
+                {
+                    Some(block) => Response::ok(block),
+                    None => Response::error(404, "block not found"),
+                }
+            }
+            ("GET", ["balance", address]) => {
+                let chain = self.chain.lock().unwrap();
+                Response::ok(&json!({
+                    "address": address,
+                    "balance": chain.balance(address),
+                    "available": chain.available_balance(address),
+                }))
+            }
+            ("POST", ["transaction"]) => self.new_transaction(&request.body),
+            ("POST", ["mine"]) => self.mine(),
+            (_, ["chain"] | ["block", ..] | ["balance", _] | ["transaction"] | ["mine"]) => {
+                Response::error(405, "method not allowed")
+            }
+            _ => Response::error(404, "not found"),
+        }
+    }
+
+    // Accepts a signed transaction in the same JSON form as in the blocks.
+    fn new_transaction(&self, body: &[u8]) -> Response {
+        let transaction: Transaction = match serde_json::from_slice(body) {
+            Ok(transaction) => transaction,
+            Err(e) => return Response::error(400, &format!("invalid transaction: {}", e)),
+        };
+        let res = self
+            .chain
+            .lock()
+            .unwrap()
+            .new_transaction(transaction.clone());
+        match res {
+            Ok(()) => {
+                let id = transaction.id();
+                if let Some(node) = &self.node {
+                    node.broadcast_transaction(transaction);
+                }
+                Response::ok(&json!({ "id": id }))
+            }
+            Err(e) => Response::error(400, &e.to_string()),
+        }
+    }
+
+    // Mines one block without holding the chain, like the menu does.
+    fn mine(&self) -> Response {
+        let (mut block, sealer) = self.chain.lock().unwrap().prepare_mining();
+        match sealer.seal(&mut block.header) {
+            Ok(()) => {}
+            Err(SealError::Cancelled) => {
+                return Response::error(409, "mining was cancelled by a new block")
+            }
+            Err(e) => return Response::error(409, &e.to_string()),
+        }
+
+        let res = self.chain.lock().unwrap().add_forged_block(block.clone());
+        match res {
+            Ok(_) => {
+                if let Some(node) = &self.node {
+                    node.broadcast_block(block.clone());
+                }
+                Response::ok(&block)
+            }
+            Err(e) => Response::error(409, &e.to_string()),
+        }
+    }
+}
+
+// Returns None when the request line or headers can't be parsed.
+fn read_request(stream: &TcpStream) -> io::Result<Option<Request>> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target),
+        _ => return Ok(None),
+    };
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = match value.trim().parse() {
+                    Ok(length) if length <= MAX_BODY => length,
+                    _ => return Ok(None),
+                };
+            }
+        }
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request { method, path, body }))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Error",
+    }
+}
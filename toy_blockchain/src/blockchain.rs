@@ -3,98 +3,1605 @@ extern crate serde_json;
 extern crate sha2;
 extern crate time;
 
+use ed25519_dalek::SigningKey;
 use sha2::{Digest, Sha256};
+use std::fmt;
 use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
 
-use chrono::prelude::*;
+use crate::amount::{Amount, StoredAmount};
+use crate::blocktree::{BlockTree, Insert, MAX_SIDE_BLOCKS};
+use crate::consensus::{self, Consensus, ConsensusParams, SealError, Sealer};
+use crate::encoding::{self, Encoder};
+use crate::env::{Env, Logger};
+use crate::events::{Event, Subscribers};
+use crate::keys;
+use crate::ledger::{Ledger, ROOT_ADDRESS};
+use crate::mempool::Mempool;
+use crate::merkle::{self, ProofStep};
+use crate::miner;
+use crate::pow;
+use crate::script::{self, Witness, WitnessError};
+use crate::snapshot::Snapshot;
+use crate::storage::Storage;
+
+use rand::Rng;
+
+// How far in milliseconds a received block may be stamped ahead of the local clock.
+pub const MAX_FUTURE_TIME: i64 = 2 * 60 * 60 * 1000;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct Transaction {
+#[serde(try_from = "StoredTransaction")]
+pub struct Transaction {
+    // Encoding version, left out for version 0 so legacy transactions keep their ids
+    #[serde(default, skip_serializing_if = "encoding::is_legacy")]
+    pub version: u32,
+    pub sender: String,
+    pub receiver: String,
+    pub amount: Amount,
+    // Paid to the miner, left out when zero so transactions from before fees keep their ids
+    #[serde(default, skip_serializing_if = "Amount::is_zero")]
+    pub fee: Amount,
+    pub nonce: u64,
+    pub signature: String,
+    // Replaces the signature when the sender is a lock address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub witness: Option<Witness>,
+}
+
+// A transaction as read back, whose amounts may still be floating point
+// coins if its version is 0 or 1.
+#[derive(Deserialize)]
+struct StoredTransaction {
+    #[serde(default)]
+    version: u32,
     sender: String,
     receiver: String,
+    amount: StoredAmount,
+    #[serde(default)]
+    fee: StoredAmount,
+    nonce: u64,
+    signature: String,
+    #[serde(default)]
+    witness: Option<Witness>,
+}
+
+impl TryFrom<StoredTransaction> for Transaction {
+    type Error = String;
+
+    fn try_from(stored: StoredTransaction) -> Result<Transaction, String> {
+        Ok(Transaction {
+            version: stored.version,
+            sender: stored.sender,
+            receiver: stored.receiver,
+            amount: stored.amount.into_amount(stored.version)?,
+            fee: stored.fee.into_amount(stored.version)?,
+            nonce: stored.nonce,
+            signature: stored.signature,
+            witness: stored.witness,
+        })
+    }
+}
+
+// A version 0 transaction as it was serialized when amounts were floats.
+#[derive(Serialize)]
+struct LegacyTransaction<'a> {
+    sender: &'a str,
+    receiver: &'a str,
     amount: f64,
+    #[serde(skip_serializing_if = "is_zero")]
+    fee: f64,
+    nonce: u64,
+    signature: &'a str,
+}
+
+fn is_zero(fee: &f64) -> bool {
+    *fee == 0.0
+}
+
+impl Transaction {
+    // Signs a transaction from `key`, with the nonce drawn from `rng`.
+    pub fn signed<R: Rng>(
+        rng: &mut R,
+        key: &SigningKey,
+        receiver: String,
+        amount: Amount,
+        fee: Amount,
+        version: u32,
+    ) -> Transaction {
+        let mut transaction = Transaction {
+            version,
+            sender: keys::address(key),
+            receiver,
+            amount,
+            fee,
+            nonce: rng.gen(),
+            signature: String::new(),
+            witness: None,
+        };
+        transaction.signature = keys::sign(key, &transaction.signing_bytes());
+        transaction
+    }
+
+    // An unsigned transaction spending from the lock address of `witness`,
+    // with the nonce drawn from `rng`. Its signers sign `signing_bytes` into
+    // the witness afterwards.
+    pub fn spending<R: Rng>(
+        rng: &mut R,
+        witness: Witness,
+        receiver: String,
+        amount: Amount,
+        fee: Amount,
+        version: u32,
+    ) -> Transaction {
+        Transaction {
+            version,
+            sender: witness.conditions.address(),
+            receiver,
+            amount,
+            fee,
+            nonce: rng.gen(),
+            signature: String::new(),
+            witness: Some(witness),
+        }
+    }
+
+    // The signature covers every field except the signature itself.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        if !encoding::is_legacy(&self.version) {
+            return self.encode(false);
+        }
+        let legacy = self.to_legacy();
+        let (sender, receiver, amount) = (legacy.sender, legacy.receiver, legacy.amount);
+        match self.fee.is_zero() {
+            true => serde_json::to_vec(&(sender, receiver, amount, self.nonce)),
+            false => serde_json::to_vec(&(sender, receiver, amount, legacy.fee, self.nonce)),
+        }
+        .unwrap()
+    }
+
+    // What the sender pays in total, None if it overflows.
+    pub fn cost(&self) -> Option<Amount> {
+        self.amount.checked_add(self.fee)
+    }
+
+    // Size in bytes, counted against the block size limit.
+    pub fn size(&self) -> usize {
+        match encoding::is_legacy(&self.version) {
+            true => serde_json::to_vec(&self.to_legacy()).unwrap().len(),
+            false => self.encode_witnessed().len(),
+        }
+    }
+
+    // Base units of fee per byte, only used to order the mempool.
+    pub fn fee_rate(&self) -> f64 {
+        self.fee.units() as f64 / self.size() as f64
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        keys::verify(&self.sender, &self.signing_bytes(), &self.signature)
+    }
+
+    // Checks that the sender allowed the transaction in the block at
+    // `height`: by its signature, or by a witness meeting the conditions of
+    // the lock address it spends from.
+    pub fn authorize(&self, height: u64) -> Result<(), TransactionError> {
+        let locked = script::is_lock_address(&self.sender);
+        match &self.witness {
+            None if locked => Err(TransactionError::Witness(WitnessError::Missing)),
+            None if !self.verify_signature() => Err(TransactionError::InvalidSignature),
+            None => Ok(()),
+            Some(_) if !locked => Err(TransactionError::Witness(WitnessError::Unexpected)),
+            // The JSON the ids of version 0 are hashed from has no witness
+            Some(_) if encoding::is_legacy(&self.version) => {
+                Err(TransactionError::Witness(WitnessError::Legacy))
+            }
+            Some(witness) => witness
+                .check(&self.sender, &self.signing_bytes(), height)
+                .map_err(TransactionError::Witness),
+        }
+    }
+
+    // The witness is left out, so a spend from a lock address keeps its id
+    // however it is authorized and can't be mined twice.
+    pub fn id(&self) -> String {
+        match encoding::is_legacy(&self.version) {
+            true => Chain::legacy_hash(&self.to_legacy()),
+            false => Chain::hex_to_string(&Chain::digest(&self.encode(true))),
+        }
+    }
+
+    // What the Merkle root of a block commits to: the id, or for a spend
+    // from a lock address the hash of the transaction with its witness.
+    pub fn leaf(&self) -> String {
+        match self.witness.is_some() && !encoding::is_legacy(&self.version) {
+            true => Chain::hex_to_string(&Chain::digest(&self.encode_witnessed())),
+            false => self.id(),
+        }
+    }
+
+    fn to_legacy(&self) -> LegacyTransaction<'_> {
+        LegacyTransaction {
+            sender: &self.sender,
+            receiver: &self.receiver,
+            amount: self.amount.to_legacy(),
+            fee: self.fee.to_legacy(),
+            nonce: self.nonce,
+            signature: &self.signature,
+        }
+    }
+
+    fn encode(&self, with_signature: bool) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder
+            .u32(self.version)
+            .str(&self.sender)
+            .str(&self.receiver);
+        // Version 1 encoded amounts as floating point coins
+        match self.version {
+            1 => encoder
+                .f64(self.amount.to_legacy())
+                .f64(self.fee.to_legacy()),
+            _ => encoder.u64(self.amount.units()).u64(self.fee.units()),
+        };
+        encoder.u64(self.nonce);
+        if with_signature {
+            encoder.str(&self.signature);
+        }
+        encoder.finish()
+    }
+
+    // The encoding with the witness after it, if there is one.
+    fn encode_witnessed(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        if let Some(witness) = &self.witness {
+            witness.encode(&mut encoder);
+        }
+        let mut bytes = self.encode(true);
+        bytes.extend(encoder.finish());
+        bytes
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Blockheader {
-    timestamp: i64,
-    previous_hash: String,
-    nonce: u64,
-    merkle: String,
-    difficulty: u32,
+    // Encoding version of the header and every transaction in the block
+    #[serde(default, skip_serializing_if = "encoding::is_legacy")]
+    pub version: u32,
+    pub timestamp: i64,
+    pub previous_hash: String,
+    pub nonce: u64,
+    pub merkle: String,
+    pub bits: u32,
+    // Proof-of-authority signature over the rest of the header, not part of the hash
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature: String,
+}
+
+impl Blockheader {
+    pub fn hash(&self) -> String {
+        match encoding::is_legacy(&self.version) {
+            true => Chain::legacy_hex(&self.hash_bytes()),
+            false => Chain::hex_to_string(&self.hash_bytes()),
+        }
+    }
+
+    // The digest compared against the proof-of-work target.
+    pub fn hash_bytes(&self) -> Vec<u8> {
+        if encoding::is_legacy(&self.version) {
+            let unsigned = Blockheader {
+                signature: String::new(),
+                ..self.clone()
+            };
+            return Chain::legacy_hash_bytes(&unsigned);
+        }
+        let bytes = Encoder::new()
+            .u32(self.version)
+            .i64(self.timestamp)
+            .str(&self.previous_hash)
+            .str(&self.merkle)
+            .u32(self.bits)
+            .u64(self.nonce)
+            .finish();
+        Chain::digest(&bytes)
+    }
+}
+
+// The headers of a chain from the genesis block up, taken from full blocks
+// or from the header chain of a light client.
+#[derive(Clone, Copy)]
+pub enum HeaderChain<'a> {
+    Blocks(&'a [Block]),
+    Headers(&'a [Blockheader]),
+}
+
+impl HeaderChain<'_> {
+    pub fn len(&self) -> usize {
+        match self {
+            HeaderChain::Blocks(blocks) => blocks.len(),
+            HeaderChain::Headers(headers) => headers.len(),
+        }
+    }
+
+    pub fn get(&self, height: usize) -> Option<&Blockheader> {
+        match self {
+            HeaderChain::Blocks(blocks) => blocks.get(height).map(|block| &block.header),
+            HeaderChain::Headers(headers) => headers.get(height),
+        }
+    }
+
+    pub fn last(&self) -> Option<&Blockheader> {
+        self.get(self.len().checked_sub(1)?)
+    }
+}
+
+impl<'a> From<&'a [Block]> for HeaderChain<'a> {
+    fn from(blocks: &'a [Block]) -> HeaderChain<'a> {
+        HeaderChain::Blocks(blocks)
+    }
+}
+
+impl<'a> From<&'a [Blockheader]> for HeaderChain<'a> {
+    fn from(headers: &'a [Blockheader]) -> HeaderChain<'a> {
+        HeaderChain::Headers(headers)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Block {
-    header: Blockheader,
+    pub header: Blockheader,
     count: u32,
-    transactions: Vec<Transaction>,
+    pub transactions: Vec<Transaction>,
+}
+
+impl Block {
+    pub fn hash(&self) -> String {
+        self.header.hash()
+    }
+
+    // Number of transactions, also known once they are pruned.
+    pub fn count(&self) -> usize {
+        self.count as usize
+    }
+
+    // Every valid block has a coinbase, so only a pruned block has no transactions.
+    pub fn is_pruned(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    // The block without its transactions, the header still proves they were there.
+    pub fn pruned(&self) -> Block {
+        Block {
+            header: self.header.clone(),
+            count: self.count,
+            transactions: Vec::new(),
+        }
+    }
+
+    // The Merkle path proving that the transaction with `id` is part of `header.merkle`.
+    pub fn merkle_proof(&self, id: &str) -> Option<Vec<ProofStep>> {
+        let index = self.transactions.iter().position(|t| t.id() == id)?;
+        let leaves: Vec<String> = self.transactions.iter().map(|t| t.leaf()).collect();
+        merkle::proof(&leaves, index, self.header.version)
+    }
+}
+
+// Proves to a light client that `transaction` is part of the block at
+// `height`, against the Merkle root in its header.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionProof {
+    pub height: usize,
+    pub transaction: Transaction,
+    pub steps: Vec<ProofStep>,
+}
+
+#[derive(Debug)]
+pub enum InvalidBlock {
+    PreviousHash {
+        expected: String,
+        found: String,
+    },
+    Merkle {
+        expected: String,
+        found: String,
+    },
+    ProofOfWork {
+        hash: String,
+        bits: u32,
+    },
+    Timestamp {
+        previous: i64,
+        found: i64,
+    },
+    Future {
+        now: i64,
+        found: i64,
+    },
+    Bits {
+        expected: u32,
+        found: u32,
+    },
+    Seal {
+        signer: Option<String>,
+    },
+    Coinbase,
+    Reward {
+        allowed: Amount,
+        found: Amount,
+    },
+    UnknownVersion(u32),
+    VersionDowngrade {
+        previous: u32,
+        found: u32,
+    },
+    TooManyTransactions {
+        count: usize,
+        max: usize,
+    },
+    TooLarge {
+        size: usize,
+        max: usize,
+    },
+    Transaction {
+        position: usize,
+        error: TransactionError,
+    },
+    Snapshot {
+        expected: String,
+        found: Option<String>,
+    },
+    Pruned {
+        height: usize,
+    },
+}
+
+#[derive(Debug)]
+pub struct ValidationError {
+    pub index: usize,
+    pub reason: InvalidBlock,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "block {} is invalid: ", self.index)?;
+        match &self.reason {
+            InvalidBlock::PreviousHash { expected, found } => write!(
+                f,
+                "previous hash is {} but the previous header hashes to {}",
+                found, expected
+            ),
+            InvalidBlock::Merkle { expected, found } => write!(
+                f,
+                "merkle root is {} but the transactions hash to {}",
+                found, expected
+            ),
+            InvalidBlock::ProofOfWork { hash, bits } => {
+                write!(f, "hash {} does not meet target bits {:#010x}", hash, bits)
+            }
+            InvalidBlock::Timestamp { previous, found } => write!(
+                f,
+                "timestamp {} is earlier than the previous block's {}",
+                found, previous
+            ),
+            InvalidBlock::Future { now, found } => write!(
+                f,
+                "timestamp {} is more than {} seconds ahead of the local clock {}",
+                found,
+                MAX_FUTURE_TIME / 1000,
+                now
+            ),
+            InvalidBlock::Bits { expected, found } => write!(
+                f,
+                "target bits are {:#010x} but retargeting requires {:#010x}",
+                found, expected
+            ),
+            InvalidBlock::Seal {
+                signer: Some(signer),
+            } => {
+                write!(f, "the block is not sealed by its signer {}", signer)
+            }
+            InvalidBlock::Seal { signer: None } => {
+                write!(f, "the block is not sealed by any signer")
+            }
+            InvalidBlock::Coinbase => write!(
+                f,
+                "the first transaction must be the only one sent by {}",
+                ROOT_ADDRESS
+            ),
+            InvalidBlock::Reward { allowed, found } => write!(
+                f,
+                "the coinbase pays {} but the subsidy and fees only allow {}",
+                found, allowed
+            ),
+            InvalidBlock::UnknownVersion(version) => {
+                write!(f, "encoding version {} is unknown", version)
+            }
+            InvalidBlock::VersionDowngrade { previous, found } => write!(
+                f,
+                "encoding version {} is lower than the previous block's {}",
+                found, previous
+            ),
+            InvalidBlock::TooManyTransactions { count, max } => {
+                write!(f, "{} transactions exceed the limit of {}", count, max)
+            }
+            InvalidBlock::TooLarge { size, max } => write!(
+                f,
+                "transactions take {} bytes, more than the limit of {}",
+                size, max
+            ),
+            InvalidBlock::Transaction { position, error } => {
+                write!(f, "transaction {}: {}", position, error)
+            }
+            InvalidBlock::Snapshot {
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "the block hashes to {} but the snapshot was taken at {}",
+                found, expected
+            ),
+            InvalidBlock::Snapshot {
+                expected,
+                found: None,
+            } => write!(f, "the chain ends before the snapshot block {}", expected),
+            InvalidBlock::Pruned { height } => write!(
+                f,
+                "the branch forks at or below height {}, where the chain is pruned",
+                height
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TransactionError {
+    ZeroAmount,
+    Overflow,
+    SelfTransfer,
+    Version { expected: u32, found: u32 },
+    TooLarge { size: usize, max: usize },
+    InsufficientFunds { available: Amount, amount: Amount },
+    InvalidSignature,
+    Witness(WitnessError),
+    Duplicate,
+    Storage(io::Error),
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionError::ZeroAmount => write!(f, "amount must be positive"),
+            TransactionError::Overflow => write!(f, "amount overflows a balance"),
+            TransactionError::SelfTransfer => write!(f, "sender and receiver are the same"),
+            TransactionError::Version { expected, found } => write!(
+                f,
+                "encoding version is {} but the block requires {}",
+                found, expected
+            ),
+            TransactionError::TooLarge { size, max } => write!(
+                f,
+                "transaction takes {} bytes, more than the block limit of {}",
+                size, max
+            ),
+            TransactionError::InsufficientFunds { available, amount } => write!(
+                f,
+                "insufficient funds: {} available, {} requested",
+                available, amount
+            ),
+            TransactionError::InvalidSignature => write!(f, "signature does not match the sender"),
+            TransactionError::Witness(e) => write!(f, "{}", e),
+            TransactionError::Duplicate => write!(f, "transaction was already submitted"),
+            TransactionError::Storage(e) => write!(f, "unable to store transaction: {}", e),
+        }
+    }
+}
+
+// Consensus parameters, every node validating the chain has to use the same ones.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Params {
+    // Difficulty is recomputed every `retarget_interval` blocks, 0 disables retargeting.
+    pub retarget_interval: u64,
+    // Target time between blocks in milliseconds.
+    pub target_block_time: i64,
+    // Limits on the transactions of a block, not counting the coinbase.
+    pub max_block_transactions: usize,
+    pub max_block_size: usize,
+    // Encoding version of new blocks and transactions. Chains created before
+    // versioning have no value stored and keep the legacy version 0.
+    #[serde(default)]
+    pub version: u32,
+    // Coinbase subsidy of the first block. Chains created before the emission
+    // schedule have none stored, their coinbase is only limited by the reward
+    // their miner chose.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subsidy: Option<Amount>,
+    // The subsidy halves every `halving_interval` blocks, 0 keeps it constant.
+    pub halving_interval: u64,
+    // Subsidies stop once they add up to `max_supply`.
+    pub max_supply: Amount,
+    pub consensus: ConsensusParams,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            retarget_interval: 10,
+            target_block_time: 10_000,
+            max_block_transactions: 1000,
+            max_block_size: 1_000_000,
+            version: encoding::VERSION,
+            subsidy: Amount::from_coins(100),
+            halving_interval: 1000,
+            max_supply: Amount::from_coins(21_000_000).unwrap(),
+            consensus: ConsensusParams::ProofOfWork,
+        }
+    }
+}
+
+impl Params {
+    // Subsidy of the block at `height`, None when the chain has no emission schedule.
+    pub fn block_subsidy(&self, height: u64) -> Option<Amount> {
+        let initial = self.subsidy?.units() as u128;
+        let (era, offset) = match self.halving_interval {
+            0 => (0, height),
+            interval => (height / interval, height % interval),
+        };
+
+        // What the schedule minted before `height`, ignoring the cap
+        let halved = |era: u64| initial.checked_shr(era as u32).unwrap_or(0);
+        let minted = (0..era.min(128))
+            .map(|e| halved(e) * self.halving_interval as u128)
+            .fold(halved(era) * offset as u128, u128::saturating_add);
+
+        let left = (self.max_supply.units() as u128).saturating_sub(minted);
+        Some(Amount::from_units(halved(era).min(left) as u64))
+    }
+}
+
+// What adding a block did to the best chain.
+#[derive(Debug, PartialEq)]
+pub enum BlockStatus {
+    // The block, or orphans that were waiting for it, extended the best chain
+    Extended,
+    // The best chain switched to a branch with more work, after rolling back
+    // `reverted` blocks of the old one
+    Reorganized { reverted: usize },
+    // Kept on a branch with less work than the best chain
+    Side,
+    // Kept until its parent arrives
+    Orphan,
+    // Its parent is unknown and it took less work than the tip, so it wasn't kept
+    Dropped,
+    Known,
 }
 
 pub struct Chain {
+    // The branch with the most work, from the genesis block up
     pub chain: Vec<Block>,
-    pub difficulty: u32,
-    pub current_transactions: Vec<Transaction>,
+    pub bits: u32,
+    pub params: Params,
+    pub mempool: Mempool,
     pub miner_address: String,
-    pub reward: f64,
+    pub reward: Amount,
+    pub miner_threads: usize,
+    // Seals blocks under proof of authority
+    pub signing_key: Option<SigningKey>,
+    // A snapshot is stored whenever the height reaches a multiple of it, 0 disables them
+    pub snapshot_interval: u64,
+    // The snapshot the chain was pruned at or bootstrapped from. The blocks up
+    // to its height have no transactions and the ledger starts from its state.
+    snapshot: Option<Snapshot>,
+    consensus: Arc<dyn Consensus>,
+    cancel_mining: Arc<AtomicBool>,
+    ledger: Ledger,
+    tree: BlockTree,
+    storage: Option<Storage>,
+    events: Subscribers,
+    env: Env,
 }
 
 impl Chain {
-    pub fn new(miner_address: String, reward: f64, difficulty: u32) -> Chain {
+    pub fn new(
+        miner_address: String,
+        reward: Amount,
+        bits: u32,
+        params: Params,
+        env: Env,
+    ) -> Chain {
         let mut chain = Chain {
             chain: Vec::new(),
-            difficulty,
-            current_transactions: Vec::new(),
+            bits,
+            consensus: consensus::from_params(&params),
+            params,
+            mempool: Mempool::new(),
             miner_address,
             reward,
+            miner_threads: miner::default_threads(),
+            signing_key: None,
+            snapshot_interval: 0,
+            snapshot: None,
+            cancel_mining: Arc::new(AtomicBool::new(false)),
+            ledger: Ledger::new(),
+            tree: BlockTree::new(),
+            storage: None,
+            events: Subscribers::default(),
+            env,
         };
 
         chain.generate_new_block();
         chain
     }
 
-    pub fn new_transaction(&mut self, sender: String, receiver: String, amount: f64) -> bool {
-        let transaction = Transaction {
-            sender,
-            receiver,
-            amount,
+    pub fn open(
+        storage: Storage,
+        miner_address: String,
+        reward: Amount,
+        bits: u32,
+        params: Params,
+        env: Env,
+    ) -> io::Result<Chain> {
+        let blocks = storage.load_blocks()?;
+        let mempool = Mempool::from_transactions(storage.load_pending()?);
+        let snapshot = storage.load_base_snapshot()?;
+
+        let ledger = match Chain::replay(&blocks, &params, snapshot.as_ref()) {
+            Ok(ledger) => ledger,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
         };
-        self.current_transactions.push(transaction);
 
-        true
+        let consensus = consensus::from_params(&params);
+        let mut tree = BlockTree::new();
+        for block in &blocks {
+            tree.insert(
+                block.hash(),
+                block.clone(),
+                &Chain::zero_hash(),
+                consensus.as_ref(),
+            );
+        }
+
+        let mut chain = Chain {
+            ledger,
+            tree,
+            chain: blocks,
+            bits,
+            consensus,
+            params,
+            mempool,
+            miner_address,
+            reward,
+            miner_threads: miner::default_threads(),
+            signing_key: None,
+            snapshot_interval: 0,
+            snapshot,
+            cancel_mining: Arc::new(AtomicBool::new(false)),
+            storage: Some(storage),
+            events: Subscribers::default(),
+            env,
+        };
+
+        if chain.chain.is_empty() && !chain.generate_new_block() {
+            return Err(io::Error::other("unable to store the genesis block"));
+        }
+        Ok(chain)
+    }
+
+    // Creates the chain in the empty `storage` from a snapshot taken by
+    // another node. Only the headers of its blocks are checked, the state is
+    // trusted from the snapshot and the blocks after it are validated in full.
+    pub fn bootstrap(
+        storage: Storage,
+        snapshot: Snapshot,
+        miner_address: String,
+        reward: Amount,
+        bits: u32,
+        params: Params,
+        env: Env,
+    ) -> io::Result<Chain> {
+        // The genesis block of a new chain is replaced by the blocks of the snapshot
+        if storage.load_blocks()?.len() > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the chain already has blocks after its genesis block",
+            ));
+        }
+        if snapshot.compute_commitment() != snapshot.commitment {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the snapshot doesn't match its commitment",
+            ));
+        }
+        if let Err(e) = Chain::replay(&snapshot.blocks, &params, Some(&snapshot)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()));
+        }
+
+        storage.save_base_snapshot(&snapshot)?;
+        storage.replace_blocks(&snapshot.blocks)?;
+        Chain::open(storage, miner_address, reward, bits, params, env)
+    }
+
+    pub fn new_transaction(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        Chain::check_transaction(&transaction, self.chain.len() as u64)?;
+        if transaction.version != self.params.version {
+            return Err(TransactionError::Version {
+                expected: self.params.version,
+                found: transaction.version,
+            });
+        }
+        let id = transaction.id();
+        if self.ledger.contains(&id) || self.mempool.contains(&id) {
+            return Err(TransactionError::Duplicate);
+        }
+        // It could never be mined
+        let size = transaction.size();
+        if size > self.params.max_block_size {
+            return Err(TransactionError::TooLarge {
+                size,
+                max: self.params.max_block_size,
+            });
+        }
+        // Fails if the sender can't pay on top of the pending transactions
+        self.pending_ledger().apply_transaction(&transaction)?;
+
+        self.mempool.push(transaction.clone());
+
+        if let Err(e) = self.try_save_pending() {
+            self.mempool.remove(&id);
+            return Err(TransactionError::Storage(e));
+        }
+        self.events.emit(Event::TransactionAccepted(transaction));
+        Ok(())
+    }
+
+    // Events are sent while the chain changes, so a receiver on another
+    // thread sees them in order without polling the chain.
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    // Checks that don't depend on the balances, for the block at `height`.
+    fn check_transaction(transaction: &Transaction, height: u64) -> Result<(), TransactionError> {
+        if transaction.amount.is_zero() {
+            return Err(TransactionError::ZeroAmount);
+        }
+        if transaction.sender == transaction.receiver {
+            return Err(TransactionError::SelfTransfer);
+        }
+        transaction.authorize(height)
+    }
+
+    pub fn balance(&self, address: &str) -> Amount {
+        self.ledger.balance(address)
+    }
+
+    // Confirmed balance with the pending transactions applied on top.
+    pub fn available_balance(&self, address: &str) -> Amount {
+        self.pending_ledger().balance(address)
+    }
+
+    fn pending_ledger(&self) -> Ledger {
+        let mut ledger = self.ledger.clone();
+        for transaction in self.mempool.transactions() {
+            // Pending transactions that no longer apply are dropped on the next block
+            let _ = ledger.apply_transaction(transaction);
+        }
+        ledger
     }
 
     pub fn last_hash(&self) -> String {
         let block = match self.chain.last() {
             Some(block) => block,
-            None => return String::from_utf8(vec![48; 64]).unwrap(),
+            None => return Chain::zero_hash(),
         };
-        Chain::hash(&block.header)
+        block.hash()
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        Chain::replay(&self.chain, &self.params, self.snapshot.as_ref()).map(|_| ())
+    }
+
+    // Validates `blocks` and returns the balances they result in. With a
+    // `snapshot`, only the headers of the blocks up to its height are checked
+    // and the ledger continues from its state.
+    fn replay(
+        blocks: &[Block],
+        params: &Params,
+        snapshot: Option<&Snapshot>,
+    ) -> Result<Ledger, ValidationError> {
+        let mut ledger = Ledger::new();
+        let consensus = consensus::from_params(params);
+
+        if let Some(snapshot) = snapshot {
+            if blocks.len() <= snapshot.height {
+                return Err(ValidationError {
+                    index: blocks.len(),
+                    reason: InvalidBlock::Snapshot {
+                        expected: snapshot.hash.clone(),
+                        found: None,
+                    },
+                });
+            }
+        }
+        for (index, block) in blocks.iter().enumerate() {
+            let before = &blocks[..index];
+            let res = match snapshot {
+                Some(snapshot) if index < snapshot.height => {
+                    Chain::check_header(&block.header, before.into(), consensus.as_ref())
+                }
+                Some(snapshot) if index == snapshot.height => {
+                    Chain::check_header(&block.header, before.into(), consensus.as_ref()).and_then(
+                        |()| match block.hash() == snapshot.hash {
+                            true => {
+                                ledger = snapshot.ledger();
+                                Ok(())
+                            }
+                            false => Err(InvalidBlock::Snapshot {
+                                expected: snapshot.hash.clone(),
+                                found: Some(block.hash()),
+                            }),
+                        },
+                    )
+                }
+                _ => Chain::check_block(block, before, params, consensus.as_ref(), &mut ledger),
+            };
+            if let Err(reason) = res {
+                return Err(ValidationError { index, reason });
+            }
+        }
+        Ok(ledger)
+    }
+
+    // Checks that the transactions of a block are the ones its header commits
+    // to and start with the coinbase.
+    fn check_body(block: &Block) -> Result<(), InvalidBlock> {
+        let header = &block.header;
+        let merkle = Chain::get_merkle(&block.transactions, header.version);
+        if header.merkle != merkle {
+            return Err(InvalidBlock::Merkle {
+                expected: merkle,
+                found: header.merkle.clone(),
+            });
+        }
+        match block.transactions.first() {
+            Some(coinbase) if coinbase.sender == ROOT_ADDRESS => Ok(()),
+            _ => Err(InvalidBlock::Coinbase),
+        }
+    }
+
+    // Checks a block on top of the blocks `before` it and applies its transactions to `ledger`.
+    fn check_block(
+        block: &Block,
+        before: &[Block],
+        params: &Params,
+        consensus: &dyn Consensus,
+        ledger: &mut Ledger,
+    ) -> Result<(), InvalidBlock> {
+        let header = &block.header;
+        Chain::check_header(header, before.into(), consensus)?;
+        Chain::check_body(block)?;
+
+        let count = block.transactions.len() - 1;
+        if count > params.max_block_transactions {
+            return Err(InvalidBlock::TooManyTransactions {
+                count,
+                max: params.max_block_transactions,
+            });
+        }
+        if let Some(subsidy) = params.block_subsidy(before.len() as u64) {
+            let allowed = Amount::checked_sum(block.transactions[1..].iter().map(|t| t.fee))
+                .and_then(|fees| fees.checked_add(subsidy))
+                .unwrap_or(subsidy);
+            let found = block.transactions[0].amount;
+            if found > allowed {
+                return Err(InvalidBlock::Reward { allowed, found });
+            }
+        }
+        let size = block.transactions[1..].iter().map(|t| t.size()).sum();
+        if size > params.max_block_size {
+            return Err(InvalidBlock::TooLarge {
+                size,
+                max: params.max_block_size,
+            });
+        }
+        for (position, transaction) in block.transactions.iter().enumerate() {
+            let fail = |error| Err(InvalidBlock::Transaction { position, error });
+
+            if transaction.version != header.version {
+                return fail(TransactionError::Version {
+                    expected: header.version,
+                    found: transaction.version,
+                });
+            }
+            if ledger.contains(&transaction.id()) {
+                return fail(TransactionError::Duplicate);
+            }
+            if position > 0 {
+                if transaction.sender == ROOT_ADDRESS {
+                    return Err(InvalidBlock::Coinbase);
+                }
+                let height = before.len() as u64;
+                if let Err(error) = Chain::check_transaction(transaction, height) {
+                    return fail(error);
+                }
+            }
+            if let Err(error) = ledger.apply_transaction(transaction) {
+                return fail(error);
+            }
+        }
+        Ok(())
+    }
+
+    // Checks a header on top of the headers `before` it, the part of the
+    // rules a light client can check without the transactions.
+    pub fn check_header(
+        header: &Blockheader,
+        before: HeaderChain,
+        consensus: &dyn Consensus,
+    ) -> Result<(), InvalidBlock> {
+        let previous = before.last();
+
+        if header.version > encoding::VERSION {
+            return Err(InvalidBlock::UnknownVersion(header.version));
+        }
+        if let Some(previous) = previous {
+            if header.version < previous.version {
+                return Err(InvalidBlock::VersionDowngrade {
+                    previous: previous.version,
+                    found: header.version,
+                });
+            }
+        }
+
+        let expected = match previous {
+            Some(previous) => previous.hash(),
+            None => Chain::zero_hash(),
+        };
+        if header.previous_hash != expected {
+            return Err(InvalidBlock::PreviousHash {
+                expected,
+                found: header.previous_hash.clone(),
+            });
+        }
+
+        consensus.check_header(header, before)?;
+
+        if let Some(previous) = previous {
+            if header.timestamp < previous.timestamp {
+                return Err(InvalidBlock::Timestamp {
+                    previous: previous.timestamp,
+                    found: header.timestamp,
+                });
+            }
+        }
+        Ok(())
     }
 
-    pub fn update_difficulty(&mut self, difficulty: u32) -> bool {
-        self.difficulty = difficulty;
+    // Height and block of the mined transaction with `id`.
+    pub fn find_transaction(&self, id: &str) -> Option<(usize, &Block)> {
+        self.chain
+            .iter()
+            .enumerate()
+            .find(|(_, block)| block.transactions.iter().any(|t| t.id() == id))
+    }
+
+    pub fn transaction_proof(&self, id: &str) -> Option<TransactionProof> {
+        let (height, block) = self.find_transaction(id)?;
+        let transaction = block.transactions.iter().find(|t| t.id() == id)?;
+        Some(TransactionProof {
+            height,
+            transaction: transaction.clone(),
+            steps: block.merkle_proof(id)?,
+        })
+    }
+
+    // Adds a block received from a peer. It is kept in the block tree even
+    // when it doesn't extend the tip, and the best chain switches to its
+    // branch once that has more work.
+    pub fn add_block(&mut self, block: Block) -> Result<BlockStatus, ValidationError> {
+        // The last timestamp of an interval decides the next target, so a block
+        // stamped far ahead would make the difficulty easier
+        let now = self.env.clock.now();
+        if block.header.timestamp > now + MAX_FUTURE_TIME {
+            let height = self.tree.height(&block.header.previous_hash);
+            return Err(ValidationError {
+                index: height.map_or(0, |height| height + 1),
+                reason: InvalidBlock::Future {
+                    now,
+                    found: block.header.timestamp,
+                },
+            });
+        }
+        self.insert_block(block)
+    }
+
+    fn insert_block(&mut self, block: Block) -> Result<BlockStatus, ValidationError> {
+        let hash = block.hash();
+        if self.tree.contains(&hash) {
+            return Ok(BlockStatus::Known);
+        }
+        // Orphans wait for their parent, so at least their seal has to be valid
+        let parent = &block.header.previous_hash;
+        let height = match *parent == Chain::zero_hash() {
+            true => Some(0),
+            false => self.tree.height(parent).map(|height| height + 1),
+        };
+        if let Err(reason) = self.consensus.check_seal(&block.header, height) {
+            let index = height.unwrap_or(0);
+            return Err(ValidationError { index, reason });
+        }
+        // Blocks are known by the hash of their header, so a copy with other
+        // transactions must not take the place of the real block
+        if let Err(reason) = Chain::check_body(&block) {
+            let index = height.unwrap_or(0);
+            return Err(ValidationError { index, reason });
+        }
+        // Orphans can't be checked against their branch yet, so only the ones
+        // that took at least as much work as the tip are kept
+        if let (None, Some(tip)) = (height, self.chain.last()) {
+            if self.consensus.work(&block.header) < self.consensus.work(&tip.header) {
+                return Ok(BlockStatus::Dropped);
+            }
+        }
+
+        let root = Chain::zero_hash();
+        let connected = match self
+            .tree
+            .insert(hash.clone(), block, &root, self.consensus.as_ref())
+        {
+            Insert::Known => return Ok(BlockStatus::Known),
+            Insert::Orphan => return Ok(BlockStatus::Orphan),
+            Insert::Connected(connected) => connected,
+        };
+        // Blocks off the best chain are only validated in full once their
+        // branch wins, but their headers have to follow the rules of their
+        // branch, so a side branch costs as much work as the best chain
+        let mut kept = Vec::new();
+        for connected in connected {
+            if !self.tree.contains(&connected) {
+                continue;
+            }
+            let block = self.tree.get(&connected).unwrap();
+            let height = self.tree.height(&connected).unwrap();
+            if let Err(reason) = self.check_branch_header(&block.header, height) {
+                self.tree.remove(&connected);
+                if connected == hash {
+                    return Err(ValidationError {
+                        index: height,
+                        reason,
+                    });
+                }
+                continue;
+            }
+            kept.push(connected);
+        }
+
+        let best = match kept
+            .into_iter()
+            .max_by(|a, b| self.tree.work(a).cmp(&self.tree.work(b)))
+        {
+            Some(best) => best,
+            None => return Ok(BlockStatus::Side),
+        };
+        let tip_work = self
+            .tree
+            .work(&self.last_hash())
+            .cloned()
+            .unwrap_or_default();
+        if self.tree.work(&best).unwrap() <= &tip_work {
+            let chain = &self.chain;
+            let max = chain.len() + MAX_SIDE_BLOCKS;
+            self.tree
+                .evict(max, |hash, height| on_best_chain(chain, hash, height));
+            return Ok(BlockStatus::Side);
+        }
+        self.switch_to(&best)
+    }
+
+    // Checks the header of a block in the tree at `height` against the
+    // headers of its branch.
+    fn check_branch_header(&self, header: &Blockheader, height: usize) -> Result<(), InvalidBlock> {
+        let consensus = self.consensus.as_ref();
+        let mut branch = Vec::new();
+        let mut hash = header.previous_hash.clone();
+        let mut fork = height;
+        while fork > 0 && !on_best_chain(&self.chain, &hash, fork - 1) {
+            let parent = self.tree.get(&hash).unwrap();
+            hash = parent.header.previous_hash.clone();
+            branch.push(parent.header.clone());
+            fork -= 1;
+        }
+        if branch.is_empty() {
+            return Chain::check_header(header, self.chain[..height].into(), consensus);
+        }
+        branch.reverse();
+        let mut headers: Vec<Blockheader> = self.chain[..fork]
+            .iter()
+            .map(|block| block.header.clone())
+            .collect();
+        headers.extend(branch);
+        Chain::check_header(header, headers.as_slice().into(), consensus)
+    }
+
+    // Adds a block sealed from `prepare_mining` and tells the subscribers it was forged here.
+    // Its timestamp comes from the local clock.
+    pub fn add_forged_block(&mut self, block: Block) -> Result<BlockStatus, ValidationError> {
+        let status = self.insert_block(block.clone())?;
+        if matches!(
+            status,
+            BlockStatus::Extended | BlockStatus::Reorganized { .. }
+        ) {
+            self.events.emit(Event::BlockForged(block));
+        }
+        Ok(status)
+    }
+
+    // Adds the blocks of another node's chain. Returns whether the best chain changed.
+    pub fn add_blocks(&mut self, blocks: Vec<Block>) -> Result<bool, ValidationError> {
+        let tip = self.last_hash();
+        for block in blocks {
+            self.add_block(block)?;
+        }
+        Ok(self.last_hash() != tip)
+    }
+
+    // Adds blocks exported from another chain. Unlike blocks from peers they
+    // are all validated in full first, on top of the blocks of the best chain
+    // they build on, so an invalid block is reported even on a side branch.
+    // Returns whether the best chain changed.
+    pub fn import_blocks(&mut self, blocks: Vec<Block>) -> Result<bool, ValidationError> {
+        let start = match blocks.first() {
+            Some(first) => self
+                .chain
+                .iter()
+                .position(|block| block.hash() == first.header.previous_hash)
+                .map_or(0, |height| height + 1),
+            None => return Ok(false),
+        };
+        let mut candidate = self.chain[..start].to_vec();
+        candidate.extend(blocks.iter().cloned());
+        Chain::replay(&candidate, &self.params, self.snapshot.as_ref())?;
+        self.add_blocks(blocks)
+    }
+
+    fn on_best_chain(&self, hash: &str, height: usize) -> bool {
+        on_best_chain(&self.chain, hash, height)
+    }
+
+    // Makes the branch ending in `tip` the best chain. The blocks after the
+    // fork are rolled back out of the ledger and the new ones are validated
+    // and applied in their place. When one of them is invalid, it is dropped
+    // with its descendants and the old branch is restored.
+    fn switch_to(&mut self, tip: &str) -> Result<BlockStatus, ValidationError> {
+        let mut branch = Vec::new();
+        let mut hash = tip.to_string();
+        while let Some(height) = self.tree.height(&hash) {
+            if self.on_best_chain(&hash, height) {
+                break;
+            }
+            let block = self.tree.get(&hash).unwrap().clone();
+            hash = block.header.previous_hash.clone();
+            branch.push(block);
+        }
+        branch.reverse();
+        let fork = self.tree.height(tip).unwrap() + 1 - branch.len();
+        let (bits, reward) = (self.next_bits(), self.next_reward());
+        // The transactions needed to roll back the old branch are gone
+        if let Some(snapshot) = &self.snapshot {
+            if fork <= snapshot.height {
+                self.tree.remove(&branch[0].hash());
+                return Err(ValidationError {
+                    index: fork,
+                    reason: InvalidBlock::Pruned {
+                        height: snapshot.height,
+                    },
+                });
+            }
+        }
+
+        let backup = self.ledger.clone();
+        let reverted = self.chain.split_off(fork);
+        for block in reverted.iter().rev() {
+            for transaction in block.transactions.iter().rev() {
+                self.ledger.revert_transaction(transaction);
+            }
+        }
+
+        for block in branch {
+            let index = self.chain.len();
+            let res = Chain::check_block(
+                &block,
+                &self.chain,
+                &self.params,
+                self.consensus.as_ref(),
+                &mut self.ledger,
+            );
+            if let Err(reason) = res {
+                self.tree.remove(&block.hash());
+                self.chain.truncate(fork);
+                self.chain.extend(reverted);
+                self.ledger = backup;
+                return Err(ValidationError { index, reason });
+            }
+            self.chain.push(block);
+        }
+
+        if let Some(storage) = &self.storage {
+            let res = match reverted.is_empty() {
+                true => self.chain[fork..]
+                    .iter()
+                    .try_for_each(|block| storage.append_block(block)),
+                false => storage.replace_blocks(&self.chain),
+            };
+            if let Err(e) = res {
+                self.env
+                    .logger
+                    .error(&format!("Unable to store blocks: {}", e));
+            }
+        }
+        self.take_periodic_snapshot(fork);
+
+        // Transactions of the rolled back blocks are pending again, unless the
+        // new branch mined them too
+        let mut pending: Vec<Transaction> = reverted
+            .iter()
+            .flat_map(|block| block.transactions[1..].iter().cloned())
+            .collect();
+        pending.extend(self.mempool.transactions().iter().cloned());
+        self.mempool = Mempool::from_transactions(pending);
+        self.cancel_mining.store(true, Ordering::Relaxed);
+        self.refresh_pending();
+
+        if !reverted.is_empty() {
+            self.events.emit(Event::Reorganized {
+                fork,
+                reverted: reverted.iter().map(Block::hash).collect(),
+                connected: self.chain[fork..].iter().map(Block::hash).collect(),
+            });
+        }
+        for (height, block) in self.chain.iter().enumerate().skip(fork) {
+            let hash = block.hash();
+            self.events.emit(Event::BlockAdded { height, hash });
+        }
+        self.emit_changes(bits, reward);
+
+        Ok(match reverted.len() {
+            0 => BlockStatus::Extended,
+            reverted => BlockStatus::Reorganized { reverted },
+        })
+    }
+
+    // The state after the block at `height`, None when the height is past the
+    // tip or below the snapshot the chain was pruned at.
+    pub fn snapshot(&self, height: usize) -> Option<Snapshot> {
+        if height + 1 == self.chain.len() {
+            return Some(Snapshot::new(&self.chain, &self.ledger));
+        }
+        let blocks = self.chain.get(..=height)?;
+        let ledger = Chain::replay(blocks, &self.params, self.snapshot.as_ref()).ok()?;
+        Some(Snapshot::new(blocks, &ledger))
+    }
+
+    // Where the chain reports what it is doing, for whatever runs alongside it.
+    pub fn logger(&self) -> Arc<dyn Logger> {
+        self.env.logger.clone()
+    }
+
+    // Height of the snapshot the chain was pruned at or bootstrapped from.
+    pub fn pruned_height(&self) -> Option<usize> {
+        self.snapshot.as_ref().map(|snapshot| snapshot.height)
+    }
+
+    // Drops the transactions of the blocks up to the height of `snapshot`,
+    // which has to be taken from this chain. The chain can't reorganize below
+    // that height anymore.
+    pub fn prune(&mut self, snapshot: Snapshot) -> io::Result<()> {
+        if self.chain.get(snapshot.height).map(|block| block.hash()) != Some(snapshot.hash.clone())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the snapshot is not of a block of this chain",
+            ));
+        }
+        let mut chain = self.chain.clone();
+        for block in &mut chain[..=snapshot.height] {
+            *block = block.pruned();
+        }
+        if let Some(storage) = &self.storage {
+            storage.save_base_snapshot(&snapshot)?;
+            storage.replace_blocks(&chain)?;
+        }
+        self.chain = chain;
+        self.snapshot = Some(snapshot);
+        Ok(())
+    }
+
+    // Stores a snapshot when a block from `from` up to the tip reached a
+    // multiple of the snapshot interval.
+    fn take_periodic_snapshot(&self, from: usize) {
+        let interval = self.snapshot_interval as usize;
+        let storage = match &self.storage {
+            Some(storage) if interval > 0 => storage,
+            _ => return,
+        };
+        let due = (from.max(1)..self.chain.len())
+            .rev()
+            .find(|height| height.is_multiple_of(interval));
+        if let Some(snapshot) = due.and_then(|height| self.snapshot(height)) {
+            if let Err(e) = storage.save_snapshot(&snapshot) {
+                self.env.logger.error(&format!(
+                    "Unable to store the snapshot of block {}: {}",
+                    snapshot.height, e
+                ));
+            }
+        }
+    }
+
+    // Drops pending transactions that were mined or are no longer valid on top of the chain.
+    fn refresh_pending(&mut self) {
+        let height = self.chain.len() as u64;
+        self.mempool
+            .refresh(&self.ledger, self.params.version, height);
+        if let Err(e) = self.try_save_pending() {
+            self.env
+                .logger
+                .error(&format!("Unable to store pending transactions: {}", e));
+        }
+    }
+
+    // Target bits the block after `blocks` must have, None when any valid target is accepted.
+    // Every `retarget_interval` blocks the target is scaled by how long the last
+    // interval took compared to `target_block_time`, in between it stays the same.
+    pub fn required_bits(blocks: HeaderChain, params: &Params) -> Option<u32> {
+        let interval = params.retarget_interval as usize;
+        let previous = blocks.last()?;
+        if interval == 0 {
+            return None;
+        }
+
+        let height = blocks.len();
+        if !height.is_multiple_of(interval) {
+            return Some(previous.bits);
+        }
+        let first = blocks.get(height - interval)?;
+        let actual = previous.timestamp - first.timestamp;
+        let expected = params.target_block_time * (interval as i64 - 1);
+        Some(pow::retarget(previous.bits, actual, expected))
+    }
+
+    // Target bits for the next mined block.
+    pub fn next_bits(&self) -> u32 {
+        Chain::required_bits(self.chain.as_slice().into(), &self.params).unwrap_or(self.bits)
+    }
+
+    pub fn update_difficulty(&mut self, bits: u32) -> bool {
+        if self.params.retarget_interval > 0 || pow::target_from_bits(bits).is_none() {
+            return false;
+        }
+        let previous = self.next_bits();
+        self.bits = bits;
+        self.emit_changes(previous, self.next_reward());
         true
     }
 
-    pub fn update_reward(&mut self, reward: f64) -> bool {
+    // Subsidy of the next mined block, without fees.
+    pub fn next_reward(&self) -> Amount {
+        self.params
+            .block_subsidy(self.chain.len() as u64)
+            .unwrap_or(self.reward)
+    }
+
+    pub fn update_reward(&mut self, reward: Amount) -> bool {
+        if self.params.subsidy.is_some() {
+            return false;
+        }
+        let previous = self.next_reward();
         self.reward = reward;
+        self.emit_changes(self.next_bits(), previous);
         true
     }
 
+    // Tells the subscribers when the target or the subsidy of the next block
+    // is no longer `bits` and `reward`.
+    fn emit_changes(&mut self, bits: u32, reward: Amount) {
+        let next_bits = self.next_bits();
+        if next_bits != bits {
+            self.events
+                .emit(Event::DifficultyChanged { bits: next_bits });
+        }
+        let next_reward = self.next_reward();
+        if next_reward != reward {
+            self.events.emit(Event::RewardChanged {
+                reward: next_reward,
+            });
+        }
+    }
+
     pub fn generate_new_block(&mut self) -> bool {
+        let (mut block, sealer) = self.prepare_mining();
+        match sealer.seal(&mut block.header) {
+            Ok(()) => {}
+            Err(SealError::Cancelled) => return false,
+            Err(e) => {
+                self.env
+                    .logger
+                    .error(&format!("Unable to seal the block: {}", e));
+                return false;
+            }
+        }
+
+        self.env
+            .logger
+            .info(&format!("New Block Forged: {:#?}", &block));
+
+        match self.add_forged_block(block) {
+            Ok(_) => true,
+            Err(e) => {
+                self.env
+                    .logger
+                    .error(&format!("Mined block was rejected: {}", e));
+                false
+            }
+        }
+    }
+
+    // Builds the next block to mine and resets the cancel flag. The flag is
+    // set again as soon as the tip changes, so mining can run without holding
+    // the chain and stop once its candidate is stale.
+    pub fn prepare_mining(&self) -> (Block, Sealer) {
+        self.cancel_mining.store(false, Ordering::Relaxed);
+        let sealer = Sealer {
+            consensus: self.consensus.clone(),
+            key: self.signing_key.clone(),
+            height: self.chain.len(),
+            // The genesis block is mined on one thread, so chains created
+            // with the same settings and clock share it
+            threads: match self.chain.is_empty() {
+                true => 1,
+                false => self.miner_threads,
+            },
+            cancel: self.cancel_mining.clone(),
+            logger: self.env.logger.clone(),
+        };
+        (self.candidate_block(), sealer)
+    }
+
+    pub fn mining_cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancel_mining.clone()
+    }
+
+    fn candidate_block(&self) -> Block {
+        let version = self.params.version;
         let header = Blockheader {
-            timestamp: Utc::now().timestamp_millis(),
+            version,
+            timestamp: self.env.clock.now(),
             nonce: 0,
             previous_hash: self.last_hash(),
             merkle: String::new(),
-            difficulty: self.difficulty,
+            bits: self.next_bits(),
+            signature: String::new(),
         };
 
+        let height = self.chain.len() as u64;
+        let transactions = self.mempool.select(&self.ledger, &self.params, height);
+        let subsidy = self.next_reward();
+        let reward = Amount::checked_sum(transactions.iter().map(|t| t.fee))
+            .and_then(|fees| subsidy.checked_add(fees))
+            .unwrap_or(subsidy);
+
+        // The coinbase needs no signature, the block height keeps its id unique
         let reward_trans = Transaction {
-            sender: String::from("Root"),
+            version,
+            sender: String::from(ROOT_ADDRESS),
             receiver: self.miner_address.clone(),
-            amount: self.reward,
+            amount: reward,
+            fee: Amount::ZERO,
+            nonce: self.chain.len() as u64,
+            signature: String::new(),
+            witness: None,
         };
 
         let mut block = Block {
@@ -104,80 +1611,502 @@ impl Chain {
         };
 
         block.transactions.push(reward_trans);
-        block.transactions.append(&mut self.current_transactions);
+        block.transactions.extend(transactions);
         block.count = block.transactions.len() as u32;
-        block.header.merkle = Chain::get_merkle(block.transactions.clone());
-        Chain::proof_of_work(&mut block.header);
+        block.header.merkle = Chain::get_merkle(&block.transactions, version);
+        block
+    }
 
-        println!("New Block Forged: {:#?}", &block);
+    fn try_save_pending(&self) -> io::Result<()> {
+        match &self.storage {
+            Some(storage) => storage.save_pending(self.mempool.transactions()),
+            None => Ok(()),
+        }
+    }
 
-        self.chain.push(block);
-        true
+    fn get_merkle(transactions: &[Transaction], version: u32) -> String {
+        let leaves: Vec<String> = transactions.iter().map(|t| t.leaf()).collect();
+        merkle::root(&leaves, version).unwrap_or_else(Chain::zero_hash)
+    }
+
+    fn zero_hash() -> String {
+        String::from_utf8(vec![48; 64]).unwrap()
     }
 
-    fn get_merkle(transactions: Vec<Transaction>) -> String {
-        let mut merkle: Vec<String> = Vec::new();
+    pub fn digest(bytes: &[u8]) -> Vec<u8> {
+        let mut hasher = Sha256::default();
+        hasher.update(bytes);
+        hasher.finalize().to_vec()
+    }
 
-        if transactions.is_empty() {
-            return String::from_utf8(vec![48; 64]).unwrap();
+    pub fn hex_to_string(bytes: &[u8]) -> String {
+        let mut s = String::new();
+        for b in bytes {
+            write!(&mut s, "{:02x}", b).expect("Unable to write");
         }
+        s
+    }
+
+    // Version 0 hashing: SHA-256 of the JSON form, written as hex without
+    // zero padding. Only kept so chains from before versioning still validate.
+    pub fn legacy_hash<T: serde::Serialize>(item: &T) -> String {
+        Chain::legacy_hex(&Chain::legacy_hash_bytes(item))
+    }
+
+    pub fn legacy_hash_bytes<T: serde::Serialize>(item: &T) -> Vec<u8> {
+        Chain::digest(serde_json::to_string(item).unwrap().as_bytes())
+    }
+
+    pub fn legacy_hex(bytes: &[u8]) -> String {
+        let mut s = String::new();
+        for b in bytes {
+            write!(&mut s, "{:x}", b).expect("Unable to write");
+        }
+        s
+    }
+}
+
+// Whether the block with `hash` at `height` is on the best chain `chain`.
+fn on_best_chain(chain: &[Block], hash: &str, height: usize) -> bool {
+    match chain.get(height + 1) {
+        Some(next) => next.header.previous_hash == hash,
+        None => height + 1 == chain.len() && chain[height].hash() == hash,
+    }
+}
 
-        for t in &transactions {
-            let hash = Chain::hash(t);
-            merkle.push(hash);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocktree::MAX_ORPHANS;
+    use crate::env::{FixedClock, QuietLogger};
+    use crate::script::Condition;
+    use proptest::prelude::*;
+    use rand::SeedableRng;
+    use std::collections::BTreeMap;
+
+    fn test_chain(zero_bits: u32) -> Chain {
+        let env = Env {
+            clock: Arc::new(FixedClock::new(1_700_000_000_000, 1_000)),
+            logger: Arc::new(QuietLogger),
+        };
+        let bits = pow::bits_from_zero_bits(zero_bits).unwrap();
+        let reward = Amount::from_coins(100).unwrap();
+        Chain::new("miner".to_string(), reward, bits, Params::default(), env)
+    }
+
+    fn reseal(header: &mut Blockheader) {
+        header.nonce = 0;
+        while !pow::meets_target(&header.hash_bytes(), header.bits) {
+            header.nonce += 1;
         }
+    }
 
-        if merkle.len() % 2 != 1 {
-            let last = merkle.last().cloned().unwrap();
-            merkle.push(last);
+    // A block on top of `parent` with its body, only the header is new.
+    fn side_block(parent: &Block, bits: u32, timestamp: i64) -> Block {
+        let mut header = Blockheader {
+            timestamp,
+            previous_hash: parent.hash(),
+            bits,
+            ..parent.header.clone()
+        };
+        reseal(&mut header);
+        // The parent's transactions match the merkle root of its header
+        Block {
+            header,
+            ..parent.clone()
         }
+    }
 
-        while merkle.len() > 1 {
-            let mut h1 = merkle.remove(0);
-            let mut h2 = merkle.remove(0);
-            h1.push_str(&mut h2);
-            let new_hash = Chain::hash(&h1);
-            merkle.push(new_hash);
+    fn mined_chain(blocks: usize) -> Chain {
+        let mut chain = test_chain(4);
+        chain.miner_threads = 1;
+        while chain.chain.len() < blocks {
+            assert!(chain.generate_new_block());
         }
-        merkle.pop().unwrap()
+        chain
     }
 
-    pub fn proof_of_work(header: &mut Blockheader) {
-        loop {
-            let hash = Chain::hash(header);
-            let slice = &hash[..header.difficulty as usize];
-            match slice.parse::<u32>() {
-                Ok(val) => {
-                    if val == 0 {
-                        println!("Proof of work found: {}", hash);
-                        break;
-                    } else {
-                        header.nonce += 1;
-                    }
-                }
-                Err(_) => {
-                    header.nonce += 1;
-                    continue;
+    // Tampers with the block at height 2 of a valid chain and returns the validation error.
+    fn tampered(tamper: fn(&mut Block, &Block)) -> ValidationError {
+        let mut chain = mined_chain(4);
+        assert!(chain.validate().is_ok());
+        let previous = chain.chain[1].clone();
+        tamper(&mut chain.chain[2], &previous);
+        chain.validate().unwrap_err()
+    }
+
+    #[test]
+    fn validate_finds_the_first_invalid_block() {
+        let err = tampered(|block, _| {
+            block.header.previous_hash = Chain::zero_hash();
+            reseal(&mut block.header);
+        });
+        assert_eq!(err.index, 2);
+        assert!(matches!(err.reason, InvalidBlock::PreviousHash { .. }));
+
+        let err = tampered(|block, _| {
+            block.transactions[0].amount = Amount::from_units(1);
+            reseal(&mut block.header);
+        });
+        assert_eq!(err.index, 2);
+        assert!(matches!(err.reason, InvalidBlock::Merkle { .. }));
+
+        let err = tampered(|block, _| {
+            while pow::meets_target(&block.header.hash_bytes(), block.header.bits) {
+                block.header.nonce += 1;
+            }
+        });
+        assert_eq!(err.index, 2);
+        assert!(matches!(err.reason, InvalidBlock::ProofOfWork { .. }));
+
+        let err = tampered(|block, previous| {
+            block.header.timestamp = previous.header.timestamp - 1;
+            reseal(&mut block.header);
+        });
+        assert_eq!(err.index, 2);
+        assert!(matches!(err.reason, InvalidBlock::Timestamp { .. }));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        // Tampering with any block after the genesis block is reported at that block
+        #[test]
+        fn validate_reports_any_tampered_block(
+            length in 2usize..6,
+            at in any::<prop::sample::Index>(),
+            rule in 0..4,
+        ) {
+            let mut chain = test_chain(1);
+            chain.miner_threads = 1;
+            while chain.chain.len() < length {
+                prop_assert!(chain.generate_new_block());
+            }
+            let index = 1 + at.index(length - 1);
+            let previous = chain.chain[index - 1].header.clone();
+            let block = &mut chain.chain[index];
+            match rule {
+                0 => block.header.previous_hash = Chain::zero_hash(),
+                1 => block.transactions[0].amount = Amount::from_units(1),
+                2 => block.header.timestamp = previous.timestamp - 1,
+                _ => {}
+            }
+            reseal(&mut block.header);
+            if rule == 3 {
+                while pow::meets_target(&block.header.hash_bytes(), block.header.bits) {
+                    block.header.nonce += 1;
                 }
             }
+
+            let err = chain.validate().unwrap_err();
+            prop_assert_eq!(err.index, index);
+            let reason = match rule {
+                0 => matches!(err.reason, InvalidBlock::PreviousHash { .. }),
+                1 => matches!(err.reason, InvalidBlock::Merkle { .. }),
+                2 => matches!(err.reason, InvalidBlock::Timestamp { .. }),
+                _ => matches!(err.reason, InvalidBlock::ProofOfWork { .. }),
+            };
+            prop_assert!(reason, "{}", err);
         }
     }
 
-    pub fn hash<T: serde::Serialize>(item: &T) -> String {
-        let input = serde_json::to_string(item).unwrap();
-        let mut hasher = Sha256::default();
-        hasher.update(input.as_bytes());
-        let result = hasher.finalize();
-        let vec_result = result.to_vec();
+    #[test]
+    fn subsidies_halve_up_to_the_cap() {
+        let coins = |coins| Amount::from_coins(coins).unwrap();
+        let params = |halving_interval, max_supply| Params {
+            subsidy: Some(coins(50)),
+            halving_interval,
+            max_supply,
+            ..Params::default()
+        };
 
-        Chain::hex_to_string(vec_result.as_slice())
+        // 10 blocks of 50 coins, then 25 coins until 710 coins are minted
+        let capped = params(10, coins(710));
+        let cases = [
+            (0, coins(50)),
+            (9, coins(50)),
+            (10, coins(25)),
+            (17, coins(25)),
+            (18, coins(10)),
+            (19, Amount::ZERO),
+            (1_000_000, Amount::ZERO),
+        ];
+        for (height, subsidy) in cases {
+            assert_eq!(capped.block_subsidy(height), Some(subsidy), "{}", height);
+        }
+
+        // 50 coins are 5 * 10^9 units, which halve to a single unit in the 33rd era
+        let uncapped = params(10, Amount::from_units(u64::MAX));
+        let cases = [
+            (319, Amount::from_units(2)),
+            (320, Amount::from_units(1)),
+            (329, Amount::from_units(1)),
+            (330, Amount::ZERO),
+            (u64::MAX, Amount::ZERO),
+        ];
+        for (height, subsidy) in cases {
+            assert_eq!(uncapped.block_subsidy(height), Some(subsidy), "{}", height);
+        }
+
+        let constant = params(0, coins(120));
+        let cases = [
+            (0, coins(50)),
+            (1, coins(50)),
+            (2, coins(20)),
+            (3, Amount::ZERO),
+        ];
+        for (height, subsidy) in cases {
+            assert_eq!(constant.block_subsidy(height), Some(subsidy), "{}", height);
+        }
+
+        let unscheduled = Params {
+            subsidy: None,
+            ..Params::default()
+        };
+        assert_eq!(unscheduled.block_subsidy(0), None);
     }
 
-    pub fn hex_to_string(bytes: &[u8]) -> String {
-        let mut s = String::new();
-        for b in bytes {
-            write!(&mut s, "{:x}", b).expect("Unable to write");
+    #[test]
+    fn coinbase_pays_at_most_the_subsidy_and_fees() {
+        let err = tampered(|block, _| {
+            let coinbase = &mut block.transactions[0];
+            coinbase.amount = coinbase.amount.checked_add(Amount::from_units(1)).unwrap();
+            block.header.merkle = Chain::get_merkle(&block.transactions, block.header.version);
+            reseal(&mut block.header);
+        });
+        assert_eq!(err.index, 2);
+        assert!(matches!(err.reason, InvalidBlock::Reward { .. }));
+    }
+
+    #[test]
+    fn witnesses_dont_change_the_id() {
+        let rng = &mut rand::rngs::StdRng::seed_from_u64(1);
+        let keys = [keys::generate_keypair(rng), keys::generate_keypair(rng)];
+        let conditions = Condition::Multisig {
+            required: 1,
+            signers: keys.iter().map(keys::address).collect(),
+        };
+        let witness = Witness {
+            conditions,
+            signatures: BTreeMap::new(),
+            preimage: None,
+        };
+        let amount = Amount::from_coins(1).unwrap();
+        let receiver = "bob".to_string();
+        let unsigned = Transaction::spending(rng, witness, receiver, amount, Amount::ZERO, 2);
+        let message = unsigned.signing_bytes();
+        let signed_by = |keys: &[SigningKey]| {
+            let mut transaction = unsigned.clone();
+            let witness = transaction.witness.as_mut().unwrap();
+            for key in keys {
+                let signature = keys::sign(key, &message);
+                witness.signatures.insert(keys::address(key), signature);
+            }
+            transaction
+        };
+
+        let first = signed_by(&keys[..1]);
+        let second = signed_by(&keys[1..]);
+        assert!(first.authorize(0).is_ok() && second.authorize(0).is_ok());
+        assert_eq!(first.id(), second.id());
+        assert_ne!(first.leaf(), second.leaf());
+
+        // Signatures and preimages the conditions don't need are refused
+        let mut padded = first.clone();
+        let witness = padded.witness.as_mut().unwrap();
+        witness
+            .signatures
+            .insert("junk".to_string(), "00".to_string());
+        assert!(padded.authorize(0).is_err());
+        let mut padded = first.clone();
+        padded.witness.as_mut().unwrap().preimage = Some("junk".to_string());
+        assert!(padded.authorize(0).is_err());
+    }
+
+    #[test]
+    fn multisigs_require_one_to_all_signers() {
+        let multisig = |required, signers: &[&str]| Condition::Multisig {
+            required,
+            signers: signers.iter().map(|signer| signer.to_string()).collect(),
+        };
+        assert!(multisig(1, &["a"]).check().is_ok());
+        assert!(multisig(2, &["a", "b"]).check().is_ok());
+        for condition in [
+            multisig(0, &["a", "b"]),
+            multisig(3, &["a", "b"]),
+            multisig(2, &["a", "a"]),
+            multisig((1 << 32) + 1, &["a"]),
+            Condition::Any(vec![multisig(1, &["a"]), multisig(0, &[])]),
+        ] {
+            assert!(matches!(
+                condition.check(),
+                Err(WitnessError::Required { .. })
+            ));
         }
-        s
+
+        // Anyone could spend from a lock that requires no signatures
+        let rng = &mut rand::rngs::StdRng::seed_from_u64(1);
+        let witness = Witness {
+            conditions: multisig(0, &["a"]),
+            signatures: BTreeMap::new(),
+            preimage: None,
+        };
+        let amount = Amount::from_coins(1).unwrap();
+        let receiver = "bob".to_string();
+        let spend = Transaction::spending(rng, witness, receiver, amount, Amount::ZERO, 2);
+        assert!(spend.authorize(0).is_err());
+    }
+
+    #[test]
+    fn side_blocks_need_the_bits_of_their_branch() {
+        let mut chain = test_chain(4);
+        assert!(chain.generate_new_block());
+        let genesis = chain.chain[0].clone();
+
+        let easy = pow::bits_from_zero_bits(1).unwrap();
+        let cheap = side_block(&genesis, easy, genesis.header.timestamp + 1);
+        let err = chain.add_block(cheap).unwrap_err();
+        assert!(matches!(err.reason, InvalidBlock::Bits { .. }));
+
+        let side = side_block(&genesis, genesis.header.bits, genesis.header.timestamp + 1);
+        assert!(matches!(
+            chain.add_block(side.clone()),
+            Ok(BlockStatus::Side)
+        ));
+        let cheap = side_block(&side, easy, side.header.timestamp);
+        let err = chain.add_block(cheap).unwrap_err();
+        assert!(matches!(err.reason, InvalidBlock::Bits { .. }));
+    }
+
+    #[test]
+    fn side_blocks_are_capped() {
+        let mut chain = test_chain(1);
+        assert!(chain.generate_new_block());
+        let genesis = chain.chain[0].clone();
+
+        let mut hashes = Vec::new();
+        for offset in 0..MAX_SIDE_BLOCKS as i64 + 10 {
+            let side = side_block(
+                &genesis,
+                genesis.header.bits,
+                genesis.header.timestamp + offset,
+            );
+            hashes.push(side.hash());
+            chain.add_block(side).unwrap();
+        }
+        let kept = hashes
+            .iter()
+            .filter(|hash| chain.tree.contains(hash))
+            .count();
+        assert_eq!(kept, MAX_SIDE_BLOCKS);
+        assert!(chain.tree.contains(&chain.last_hash()));
+    }
+
+    #[test]
+    fn forged_bodies_dont_replace_side_blocks() {
+        let mut chain = test_chain(4);
+        chain.miner_address = "other".to_string();
+        assert!(chain.generate_new_block());
+        let branch = mined_chain(3);
+        assert_eq!(chain.chain[0].hash(), branch.chain[0].hash());
+
+        // Same header, other coinbase
+        let side = branch.chain[1].clone();
+        let mut forged = side.clone();
+        forged.transactions[0].receiver = "thief".to_string();
+        let err = chain.add_block(forged).unwrap_err();
+        assert_eq!(err.index, 1);
+        assert!(matches!(err.reason, InvalidBlock::Merkle { .. }));
+
+        assert!(matches!(chain.add_block(side), Ok(BlockStatus::Side)));
+        assert!(matches!(
+            chain.add_block(branch.chain[2].clone()),
+            Ok(BlockStatus::Reorganized { reverted: 1 })
+        ));
+        assert_eq!(chain.last_hash(), branch.last_hash());
+    }
+
+    #[test]
+    fn orphans_need_work_and_make_room() {
+        let mut chain = test_chain(4);
+        assert!(chain.generate_new_block());
+        let tip = chain.chain[1].clone();
+        let orphan = |bits, timestamp| {
+            let mut block = tip.clone();
+            block.header.previous_hash = "11".repeat(32);
+            block.header.bits = bits;
+            block.header.timestamp = timestamp;
+            reseal(&mut block.header);
+            block
+        };
+
+        let easy = pow::bits_from_zero_bits(1).unwrap();
+        let cheap = orphan(easy, tip.header.timestamp);
+        assert!(matches!(chain.add_block(cheap), Ok(BlockStatus::Dropped)));
+
+        // Once the buffer is full the oldest orphans make room
+        let mut hashes = Vec::new();
+        for offset in 0..MAX_ORPHANS as i64 + 1 {
+            let block = orphan(tip.header.bits, tip.header.timestamp + offset);
+            hashes.push(block.hash());
+            assert!(matches!(chain.add_block(block), Ok(BlockStatus::Orphan)));
+        }
+        assert!(!chain.tree.contains(&hashes[0]));
+        assert!(hashes[1..].iter().all(|hash| chain.tree.contains(hash)));
+    }
+
+    #[test]
+    fn blocks_from_the_future_are_refused() {
+        let mut chain = test_chain(1);
+        assert!(chain.generate_new_block());
+        let genesis = chain.chain[0].clone();
+
+        let late = genesis.header.timestamp + MAX_FUTURE_TIME + 60_000;
+        let future = side_block(&genesis, genesis.header.bits, late);
+        let err = chain.add_block(future).unwrap_err();
+        assert_eq!(err.index, 1);
+        assert!(matches!(err.reason, InvalidBlock::Future { .. }));
+
+        let soon = genesis.header.timestamp + MAX_FUTURE_TIME / 2;
+        let side = side_block(&genesis, genesis.header.bits, soon);
+        assert!(matches!(chain.add_block(side), Ok(BlockStatus::Side)));
+    }
+
+    fn transaction_json(version: u32, amount: &str) -> String {
+        format!(
+            r#"{{"version":{},"sender":"a","receiver":"b","amount":{},"nonce":1,"signature":""}}"#,
+            version, amount
+        )
+    }
+
+    // A fixed clock and one mining thread give the same blocks on every run
+    #[test]
+    fn block_hashes_are_reproducible() {
+        let mut chain = test_chain(8);
+        chain.miner_threads = 1;
+        assert!(chain.generate_new_block());
+        assert!(chain.generate_new_block());
+        let hashes: Vec<String> = chain.chain.iter().map(|block| block.hash()).collect();
+        let expected = [
+            "00f443e9a6836af2ed81ac7e5cb8aee35e591c3eff7c6ae5131cbc9ec8a21c94",
+            "00c3da462b9edb3be5310f2f880087b98cb5c8256b17cca70f57f9ca8c80b7f8",
+            "00b66846a4ba69a77465dbd36b5b8225207b97740654da21b93601dd01b0425c",
+        ];
+        assert_eq!(hashes, expected);
+    }
+
+    #[test]
+    fn float_amounts_are_coins_only_before_base_units() {
+        for version in [0, 1] {
+            let transaction: Transaction =
+                serde_json::from_str(&transaction_json(version, "1.0")).unwrap();
+            assert_eq!(transaction.amount, Amount::from_coins(1).unwrap());
+            let transaction: Transaction =
+                serde_json::from_str(&transaction_json(version, "1")).unwrap();
+            assert_eq!(transaction.amount, Amount::from_units(1));
+        }
+        assert!(serde_json::from_str::<Transaction>(&transaction_json(2, "1.0")).is_err());
+        let transaction: Transaction = serde_json::from_str(&transaction_json(2, "1")).unwrap();
+        assert_eq!(transaction.amount, Amount::from_units(1));
     }
 }
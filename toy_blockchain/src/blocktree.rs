@@ -0,0 +1,149 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use num_bigint::BigUint;
+
+use crate::blockchain::Block;
+use crate::consensus::Consensus;
+
+// Most blocks kept waiting for their parent, the oldest ones are dropped first.
+pub const MAX_ORPHANS: usize = 100;
+// Most blocks kept off the best chain, the ones with the least work are dropped.
+pub const MAX_SIDE_BLOCKS: usize = 1000;
+
+struct Entry {
+    block: Block,
+    height: usize,
+    // Work of the block and all of its ancestors
+    work: BigUint,
+}
+
+pub enum Insert {
+    Known,
+    Orphan,
+    // Hashes of the block and of the orphans that were waiting for it, parents first
+    Connected(Vec<String>),
+}
+
+// Every block the chain knows about, on the best chain or on a competing
+// branch, indexed by hash. Blocks on other branches are only validated once
+// their branch has the most work. Blocks whose parent is unknown wait in the
+// orphan buffer until it arrives.
+#[derive(Default)]
+pub struct BlockTree {
+    entries: HashMap<String, Entry>,
+    // Hashes and blocks waiting for their parent, oldest first
+    orphans: VecDeque<(String, Block)>,
+}
+
+impl BlockTree {
+    pub fn new() -> BlockTree {
+        BlockTree::default()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.entries.contains_key(hash) || self.orphans.iter().any(|(orphan, _)| orphan == hash)
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&Block> {
+        self.entries.get(hash).map(|entry| &entry.block)
+    }
+
+    pub fn height(&self, hash: &str) -> Option<usize> {
+        self.entries.get(hash).map(|entry| entry.height)
+    }
+
+    pub fn work(&self, hash: &str) -> Option<&BigUint> {
+        self.entries.get(hash).map(|entry| &entry.work)
+    }
+
+    // Takes the orphans waiting for the block with `hash` out of the buffer.
+    fn take_orphans(&mut self, hash: &str) -> VecDeque<(String, Block)> {
+        let (waiting, rest) = self
+            .orphans
+            .drain(..)
+            .partition(|(_, orphan)| orphan.header.previous_hash == hash);
+        self.orphans = rest;
+        waiting
+    }
+
+    // Adds `block`, whose hash is `hash`. Blocks pointing at `root` start a
+    // new branch, like the genesis block.
+    pub fn insert(
+        &mut self,
+        hash: String,
+        block: Block,
+        root: &str,
+        consensus: &dyn Consensus,
+    ) -> Insert {
+        if self.contains(&hash) {
+            return Insert::Known;
+        }
+        let parent = &block.header.previous_hash;
+        if parent != root && !self.entries.contains_key(parent) {
+            if self.orphans.len() == MAX_ORPHANS {
+                self.orphans.pop_front();
+            }
+            self.orphans.push_back((hash, block));
+            return Insert::Orphan;
+        }
+
+        let mut connected = Vec::new();
+        let mut pending = vec![(hash, block)];
+        while let Some((hash, block)) = pending.pop() {
+            let (height, work) = match self.entries.get(&block.header.previous_hash) {
+                Some(parent) => (parent.height + 1, parent.work.clone()),
+                None => (0, BigUint::default()),
+            };
+            let work = work + consensus.work(&block.header);
+            pending.extend(self.take_orphans(&hash));
+            self.entries.insert(
+                hash.clone(),
+                Entry {
+                    block,
+                    height,
+                    work,
+                },
+            );
+            connected.push(hash);
+        }
+        Insert::Connected(connected)
+    }
+
+    // Drops the branch tips with the least work until at most `max` blocks
+    // are left. Blocks `keep` holds on to, by hash and height, stay.
+    pub fn evict<F: Fn(&str, usize) -> bool>(&mut self, max: usize, keep: F) {
+        while self.entries.len() > max {
+            let parents: HashSet<&String> = self
+                .entries
+                .values()
+                .map(|entry| &entry.block.header.previous_hash)
+                .collect();
+            let weakest = self
+                .entries
+                .iter()
+                .filter(|(hash, entry)| !parents.contains(hash) && !keep(hash, entry.height))
+                .min_by(|(_, a), (_, b)| a.work.cmp(&b.work))
+                .map(|(hash, _)| hash.clone());
+            match weakest {
+                Some(hash) => self.entries.remove(&hash),
+                None => return,
+            };
+        }
+    }
+
+    // Drops the block with `hash` and every block built on it.
+    pub fn remove(&mut self, hash: &str) {
+        let mut pending = vec![hash.to_string()];
+        while let Some(hash) = pending.pop() {
+            self.entries.remove(&hash);
+            let orphans = self.take_orphans(&hash);
+            pending.extend(orphans.into_iter().map(|(orphan, _)| orphan));
+            pending.extend(
+                self.entries
+                    .iter()
+                    .filter(|(_, entry)| entry.block.header.previous_hash == hash)
+                    .map(|(child, _)| child.clone()),
+            );
+        }
+    }
+}
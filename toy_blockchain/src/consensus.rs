@@ -0,0 +1,230 @@
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use ed25519_dalek::SigningKey;
+use num_bigint::BigUint;
+use num_traits::One;
+
+use crate::blockchain::{Blockheader, Chain, HeaderChain, InvalidBlock, Params};
+use crate::env::Logger;
+use crate::keys;
+use crate::miner;
+use crate::pow;
+
+// How blocks are sealed, every node of a chain has to use the same rules.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum ConsensusParams {
+    #[default]
+    ProofOfWork,
+    // The signers take turns, the block at height h is sealed by signers[h % signers.len()]
+    ProofOfAuthority {
+        signers: Vec<String>,
+    },
+}
+
+#[derive(Debug)]
+pub enum SealError {
+    Cancelled,
+    NoKey,
+    NotInTurn { signer: String },
+}
+
+impl fmt::Display for SealError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SealError::Cancelled => write!(f, "sealing was cancelled"),
+            SealError::NoKey => write!(f, "no signer key is configured"),
+            SealError::NotInTurn { signer } => {
+                write!(f, "the next block has to be sealed by {}", signer)
+            }
+        }
+    }
+}
+
+pub trait Consensus: Send + Sync {
+    // Checks the seal of a header at `height`, which is None when the
+    // ancestors of the block are unknown yet.
+    fn check_seal(&self, header: &Blockheader, height: Option<usize>) -> Result<(), InvalidBlock>;
+
+    // Checks the header of the block following `before`, seal included.
+    fn check_header(&self, header: &Blockheader, before: HeaderChain) -> Result<(), InvalidBlock>;
+
+    // Seals the header of the block at `height`. `key` is the signer key of
+    // this node, if it has one.
+    fn seal(
+        &self,
+        header: &mut Blockheader,
+        height: usize,
+        key: Option<&SigningKey>,
+        threads: usize,
+        cancel: &AtomicBool,
+        logger: &dyn Logger,
+    ) -> Result<(), SealError>;
+
+    // What the block adds to the work of its branch. The branch with the most
+    // work is the best chain.
+    fn work(&self, header: &Blockheader) -> BigUint;
+}
+
+pub fn from_params(params: &Params) -> Arc<dyn Consensus> {
+    match &params.consensus {
+        ConsensusParams::ProofOfWork => Arc::new(ProofOfWork {
+            params: params.clone(),
+        }),
+        ConsensusParams::ProofOfAuthority { signers } => Arc::new(ProofOfAuthority {
+            signers: signers.clone(),
+        }),
+    }
+}
+
+// Everything needed to seal a candidate block without holding the chain.
+pub struct Sealer {
+    pub consensus: Arc<dyn Consensus>,
+    pub key: Option<SigningKey>,
+    pub height: usize,
+    pub threads: usize,
+    pub cancel: Arc<AtomicBool>,
+    pub logger: Arc<dyn Logger>,
+}
+
+impl Sealer {
+    pub fn seal(&self, header: &mut Blockheader) -> Result<(), SealError> {
+        self.consensus.seal(
+            header,
+            self.height,
+            self.key.as_ref(),
+            self.threads,
+            &self.cancel,
+            self.logger.as_ref(),
+        )
+    }
+}
+
+// Blocks are sealed by finding a nonce that makes the header hash meet the
+// target in `bits`, which is retargeted according to the params.
+pub struct ProofOfWork {
+    params: Params,
+}
+
+impl Consensus for ProofOfWork {
+    fn check_seal(&self, header: &Blockheader, _: Option<usize>) -> Result<(), InvalidBlock> {
+        match pow::meets_target(&header.hash_bytes(), header.bits) {
+            true => Ok(()),
+            false => Err(InvalidBlock::ProofOfWork {
+                hash: header.hash(),
+                bits: header.bits,
+            }),
+        }
+    }
+
+    fn check_header(&self, header: &Blockheader, before: HeaderChain) -> Result<(), InvalidBlock> {
+        if let Some(expected) = Chain::required_bits(before, &self.params) {
+            if header.bits != expected {
+                return Err(InvalidBlock::Bits {
+                    expected,
+                    found: header.bits,
+                });
+            }
+        }
+        self.check_seal(header, Some(before.len()))
+    }
+
+    fn seal(
+        &self,
+        header: &mut Blockheader,
+        _: usize,
+        _: Option<&SigningKey>,
+        threads: usize,
+        cancel: &AtomicBool,
+        logger: &dyn Logger,
+    ) -> Result<(), SealError> {
+        let (solution, stats) = miner::mine(header, threads, cancel);
+        logger.info(&format!(
+            "Hashrate: {:.0} H/s ({} hashes in {:.2?} on {} threads)",
+            stats.hashrate(),
+            stats.hashes,
+            stats.elapsed,
+            threads
+        ));
+        match solution {
+            Some(solution) => {
+                *header = solution;
+                logger.info(&format!("Proof of work found: {}", header.hash()));
+                Ok(())
+            }
+            None => {
+                logger.info("Proof of work cancelled");
+                Err(SealError::Cancelled)
+            }
+        }
+    }
+
+    fn work(&self, header: &Blockheader) -> BigUint {
+        pow::work(header.bits)
+    }
+}
+
+// Blocks are signed by the configured signers in turn, and every block
+// counts the same, so the longest chain wins. The genesis block is not
+// sealed, so every node can create one and they converge on the longest
+// signed chain.
+pub struct ProofOfAuthority {
+    signers: Vec<String>,
+}
+
+impl ProofOfAuthority {
+    fn signer(&self, height: usize) -> Option<&String> {
+        self.signers.get(height % self.signers.len().max(1))
+    }
+}
+
+impl Consensus for ProofOfAuthority {
+    fn check_seal(&self, header: &Blockheader, height: Option<usize>) -> Result<(), InvalidBlock> {
+        let message = header.hash_bytes();
+        let signed_by = |signer: &String| keys::verify(signer, &message, &header.signature);
+        let valid = match height {
+            Some(0) => true,
+            Some(height) => self.signer(height).is_some_and(signed_by),
+            None => self.signers.iter().any(signed_by),
+        };
+        match valid {
+            true => Ok(()),
+            false => Err(InvalidBlock::Seal {
+                signer: height.and_then(|height| self.signer(height).cloned()),
+            }),
+        }
+    }
+
+    fn check_header(&self, header: &Blockheader, before: HeaderChain) -> Result<(), InvalidBlock> {
+        self.check_seal(header, Some(before.len()))
+    }
+
+    fn seal(
+        &self,
+        header: &mut Blockheader,
+        height: usize,
+        key: Option<&SigningKey>,
+        _: usize,
+        _: &AtomicBool,
+        logger: &dyn Logger,
+    ) -> Result<(), SealError> {
+        if height == 0 {
+            return Ok(());
+        }
+        let signer = self.signer(height).ok_or(SealError::NoKey)?;
+        let key = key.ok_or(SealError::NoKey)?;
+        if keys::address(key) != *signer {
+            return Err(SealError::NotInTurn {
+                signer: signer.clone(),
+            });
+        }
+        header.signature = keys::sign(key, &header.hash_bytes());
+        logger.info(&format!("Block sealed by {}", signer));
+        Ok(())
+    }
+
+    fn work(&self, _: &Blockheader) -> BigUint {
+        BigUint::one()
+    }
+}
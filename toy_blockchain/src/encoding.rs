@@ -0,0 +1,53 @@
+// Canonical binary encoding of headers and transactions, hashed and signed
+// from version 1 on. Integers are big-endian and fixed width, floats are
+// their IEEE 754 bits and strings are prefixed with their length in bytes.
+// Version 0 objects are hashed from their JSON form, as before the encoding
+// existed. Version 2 encodes amounts as integer base units, where version 1
+// still wrote floating point coins.
+
+pub const LEGACY_VERSION: u32 = 0;
+pub const VERSION: u32 = 2;
+
+#[derive(Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Encoder {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Encoder {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn i64(&mut self, value: i64) -> &mut Encoder {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn f64(&mut self, value: f64) -> &mut Encoder {
+        self.u64(value.to_bits())
+    }
+
+    pub fn str(&mut self, value: &str) -> &mut Encoder {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+}
+
+pub fn is_legacy(version: &u32) -> bool {
+    *version == LEGACY_VERSION
+}
@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use chrono::Utc;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// Where the time of new blocks comes from.
+pub trait Clock: Send + Sync {
+    // Milliseconds since the Unix epoch
+    fn now(&self) -> i64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Utc::now().timestamp_millis()
+    }
+}
+
+// Starts at `start` and moves on by `step` milliseconds every time it is
+// read, so blocks get the same timestamps on every run.
+pub struct FixedClock {
+    next: AtomicI64,
+    step: i64,
+}
+
+impl FixedClock {
+    pub fn new(start: i64, step: i64) -> FixedClock {
+        FixedClock {
+            next: AtomicI64::new(start),
+            step,
+        }
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> i64 {
+        self.next.fetch_add(self.step, Ordering::Relaxed)
+    }
+}
+
+// Where a chain reports what it is doing.
+pub trait Logger: Send + Sync {
+    fn info(&self, message: &str);
+    fn error(&self, message: &str);
+}
+
+pub struct StdoutLogger;
+
+impl Logger for StdoutLogger {
+    fn info(&self, message: &str) {
+        println!("{}", message);
+    }
+
+    fn error(&self, message: &str) {
+        eprintln!("{}", message);
+    }
+}
+
+pub struct QuietLogger;
+
+impl Logger for QuietLogger {
+    fn info(&self, _: &str) {}
+
+    fn error(&self, _: &str) {}
+}
+
+// What a chain depends on besides its blocks. With a fixed clock and one
+// mining thread, the same transactions always give the same block hashes.
+#[derive(Clone)]
+pub struct Env {
+    pub clock: Arc<dyn Clock>,
+    pub logger: Arc<dyn Logger>,
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env {
+            clock: Arc::new(SystemClock),
+            logger: Arc::new(StdoutLogger),
+        }
+    }
+}
+
+// Random numbers for keys and transaction nonces. A seed makes them
+// reproducible, which is only meant for tests, since anyone can derive
+// the same keys.
+pub fn rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
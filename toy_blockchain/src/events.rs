@@ -0,0 +1,49 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::amount::Amount;
+use crate::blockchain::{Block, Transaction};
+
+// Something that changed on a chain, sent to everyone subscribed to it.
+#[derive(Serialize, Debug, Clone)]
+pub enum Event {
+    // A transaction joined the pending transactions
+    TransactionAccepted(Transaction),
+    // A block mined on this chain joined the best chain
+    BlockForged(Block),
+    // A block joined the best chain, whether it was forged here or received
+    BlockAdded {
+        height: usize,
+        hash: String,
+    },
+    // The target of the next block changed, after a retarget or by hand
+    DifficultyChanged {
+        bits: u32,
+    },
+    // The subsidy of the next block changed, after a halving or by hand
+    RewardChanged {
+        reward: Amount,
+    },
+    // The best chain switched to another branch at height `fork`. The hashes
+    // are of the blocks that left and joined the best chain.
+    Reorganized {
+        fork: usize,
+        reverted: Vec<String>,
+        connected: Vec<String>,
+    },
+}
+
+#[derive(Default)]
+pub struct Subscribers(Vec<Sender<Event>>);
+
+impl Subscribers {
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.0.push(sender);
+        receiver
+    }
+
+    // Subscribers that dropped their receiver are forgotten.
+    pub fn emit(&mut self, event: Event) {
+        self.0.retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,475 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::DateTime;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block as Panel, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::blockchain::{Block, Chain, Transaction};
+use crate::merkle;
+use crate::pow;
+
+// How often the screen is redrawn without input, to show new blocks.
+const REFRESH: Duration = Duration::from_millis(500);
+const PAGE: isize = 20;
+
+// A screen of the explorer. Each one keeps its selection, so going back
+// returns to the row that was opened.
+enum View {
+    Blocks {
+        state: TableState,
+    },
+    Block {
+        height: usize,
+        state: TableState,
+    },
+    Transaction {
+        height: usize,
+        index: usize,
+    },
+    // Transactions sent or received by `address`, as (height, index) pairs
+    Address {
+        address: String,
+        found: Vec<(usize, usize)>,
+        state: TableState,
+    },
+}
+
+struct Explorer {
+    chain: Arc<Mutex<Chain>>,
+    // Screens opened so far, the last one is shown
+    views: Vec<View>,
+    // What was typed after `/`, None when not searching
+    search: Option<String>,
+    message: Option<String>,
+}
+
+// Browses the chain in the terminal until `q` is pressed. The chain is read
+// again for every frame, so blocks mined or received meanwhile show up.
+pub fn run(chain: Arc<Mutex<Chain>>) -> io::Result<()> {
+    let tip = chain.lock().unwrap().chain.len().checked_sub(1);
+    let mut explorer = Explorer {
+        chain,
+        views: vec![View::Blocks {
+            state: TableState::default().with_selected(tip),
+        }],
+        search: None,
+        message: None,
+    };
+
+    let mut terminal = ratatui::try_init()?;
+    let res = explorer.run(&mut terminal);
+    ratatui::restore();
+    res
+}
+
+impl Explorer {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if !event::poll(REFRESH)? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                // Raw mode turns Ctrl-C into a key press
+                let interrupt =
+                    key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+                if interrupt || (key.kind == KeyEventKind::Press && !self.handle_key(key.code)) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Returns false once the explorer should close.
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        if let Some(search) = &mut self.search {
+            match code {
+                KeyCode::Enter => {
+                    let query = search.trim().to_string();
+                    self.search = None;
+                    self.search_for(&query);
+                }
+                KeyCode::Esc => self.search = None,
+                KeyCode::Backspace => {
+                    search.pop();
+                }
+                KeyCode::Char(c) => search.push(c),
+                _ => {}
+            }
+            return true;
+        }
+
+        self.message = None;
+        match code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('/') => self.search = Some(String::new()),
+            KeyCode::Esc | KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h')
+                if self.views.len() > 1 =>
+            {
+                self.views.pop();
+            }
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => self.open(),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-PAGE),
+            KeyCode::PageDown => self.move_selection(PAGE),
+            KeyCode::Home => self.move_selection(isize::MIN),
+            KeyCode::End => self.move_selection(isize::MAX),
+            _ => {}
+        }
+        true
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let chain = self.chain.lock().unwrap();
+        let (state, rows) = match self.views.last_mut().unwrap() {
+            View::Blocks { state } => (state, chain.chain.len()),
+            View::Block { height, state } => (
+                state,
+                chain.chain.get(*height).map_or(0, |b| b.transactions.len()),
+            ),
+            View::Address { found, state, .. } => (state, found.len()),
+            View::Transaction { .. } => return,
+        };
+        if rows == 0 {
+            return;
+        }
+        let current = state.selected().unwrap_or(0) as isize;
+        let next = current.saturating_add(delta).clamp(0, rows as isize - 1);
+        state.select(Some(next as usize));
+    }
+
+    // Opens the selected row.
+    fn open(&mut self) {
+        let view = match self.views.last().unwrap() {
+            View::Blocks { state } => match state.selected() {
+                Some(height) => View::Block {
+                    height,
+                    state: TableState::default().with_selected(0),
+                },
+                None => return,
+            },
+            View::Block { height, state } => match state.selected() {
+                Some(index) => View::Transaction {
+                    height: *height,
+                    index,
+                },
+                None => return,
+            },
+            View::Address { found, state, .. } => match state.selected().map(|i| found[i]) {
+                Some((height, index)) => View::Transaction { height, index },
+                None => return,
+            },
+            View::Transaction { .. } => return,
+        };
+        self.views.push(view);
+    }
+
+    // Looks `query` up as an address, then as the start of a block hash or
+    // transaction id.
+    fn search_for(&mut self, query: &str) {
+        if query.is_empty() {
+            return;
+        }
+        let chain = self.chain.lock().unwrap();
+        let blocks = &chain.chain;
+
+        let found: Vec<(usize, usize)> = transactions(blocks)
+            .filter(|(_, _, t)| t.sender == query || t.receiver == query)
+            .map(|(height, index, _)| (height, index))
+            .collect();
+        let view = if !found.is_empty() {
+            View::Address {
+                address: query.to_string(),
+                found,
+                state: TableState::default().with_selected(0),
+            }
+        } else if let Some(height) = blocks.iter().position(|b| b.hash().starts_with(query)) {
+            View::Block {
+                height,
+                state: TableState::default().with_selected(0),
+            }
+        } else if let Some((height, index, _)) =
+            transactions(blocks).find(|(_, _, t)| t.id().starts_with(query))
+        {
+            View::Transaction { height, index }
+        } else {
+            self.message = Some(format!("Nothing found for {}", query));
+            return;
+        };
+        self.views.push(view);
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let chain = self.chain.lock().unwrap();
+
+        let help = match self.views.last_mut().unwrap() {
+            View::Blocks { state } => {
+                draw_blocks(frame, main, &chain.chain, state);
+                "Up/Down select  Enter open  / search  q quit"
+            }
+            View::Block { height, state } => {
+                match chain.chain.get(*height) {
+                    Some(block) => draw_block(frame, main, *height, block, state),
+                    None => draw_missing(frame, main),
+                }
+                "Up/Down select  Enter open  Esc back  / search  q quit"
+            }
+            View::Transaction { height, index } => {
+                match chain.chain.get(*height) {
+                    Some(block) if *index < block.transactions.len() => {
+                        draw_transaction(frame, main, *height, block, *index)
+                    }
+                    _ => draw_missing(frame, main),
+                }
+                "Esc back  / search  q quit"
+            }
+            View::Address {
+                address,
+                found,
+                state,
+            } => {
+                draw_address(frame, main, &chain, address, found, state);
+                "Up/Down select  Enter open  Esc back  / search  q quit"
+            }
+        };
+
+        let status = match (&self.search, &self.message) {
+            (Some(search), _) => format!("Search address or hash: {}_", search),
+            (None, Some(message)) => message.clone(),
+            (None, None) => help.to_string(),
+        };
+        frame.render_widget(Paragraph::new(status), footer);
+    }
+}
+
+// Every transaction of the chain with its block height and position.
+fn transactions(blocks: &[Block]) -> impl Iterator<Item = (usize, usize, &Transaction)> {
+    blocks.iter().enumerate().flat_map(|(height, block)| {
+        block
+            .transactions
+            .iter()
+            .enumerate()
+            .map(move |(index, t)| (height, index, t))
+    })
+}
+
+fn timestamp(millis: i64) -> String {
+    match DateTime::from_timestamp_millis(millis) {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => millis.to_string(),
+    }
+}
+
+fn difficulty(bits: u32) -> String {
+    format!("{:.2} ({:#010x})", pow::zero_bits(bits), bits)
+}
+
+fn bold() -> Style {
+    Style::new().add_modifier(Modifier::BOLD)
+}
+
+fn selected() -> Style {
+    Style::new().add_modifier(Modifier::REVERSED)
+}
+
+fn field<'a>(name: &'a str, value: String) -> Line<'a> {
+    Line::from(format!("{:<12}{}", name, value))
+}
+
+fn draw_blocks(frame: &mut Frame, area: Rect, blocks: &[Block], state: &mut TableState) {
+    let rows = blocks.iter().enumerate().map(|(height, block)| {
+        let header = &block.header;
+        Row::new(vec![
+            height.to_string(),
+            block.hash(),
+            header.nonce.to_string(),
+            difficulty(header.bits),
+            block.count().to_string(),
+            timestamp(header.timestamp),
+        ])
+    });
+    let widths = [
+        Constraint::Length(7),
+        Constraint::Min(16),
+        Constraint::Length(20),
+        Constraint::Length(19),
+        Constraint::Length(4),
+        Constraint::Length(19),
+    ];
+    let table = Table::new(rows, widths)
+        .header(Row::new(["Height", "Hash", "Nonce", "Difficulty", "Txs", "Time"]).style(bold()))
+        .block(Panel::bordered().title(format!(" Blocks ({}) ", blocks.len())))
+        .row_highlight_style(selected());
+    frame.render_stateful_widget(table, area, state);
+}
+
+fn draw_block(frame: &mut Frame, area: Rect, height: usize, block: &Block, state: &mut TableState) {
+    let header = &block.header;
+    let mut lines = vec![
+        field("Hash", block.hash()),
+        field("Previous", header.previous_hash.clone()),
+        field("Merkle root", header.merkle.clone()),
+        field("Version", header.version.to_string()),
+        field("Difficulty", difficulty(header.bits)),
+        field("Nonce", header.nonce.to_string()),
+        field("Time", timestamp(header.timestamp)),
+        field(
+            "Reward",
+            match block.transactions.first() {
+                Some(coinbase) => coinbase.amount.to_string(),
+                None => "pruned".to_string(),
+            },
+        ),
+    ];
+    if !header.signature.is_empty() {
+        lines.push(field("Seal", header.signature.clone()));
+    }
+    let [details, list] = Layout::vertical([
+        Constraint::Length(lines.len() as u16 + 2),
+        Constraint::Min(0),
+    ])
+    .areas(area);
+    let title = format!(" Block {} ", height);
+    frame.render_widget(
+        Paragraph::new(lines).block(Panel::bordered().title(title)),
+        details,
+    );
+
+    let rows = block.transactions.iter().enumerate().map(|(index, t)| {
+        Row::new(vec![
+            index.to_string(),
+            t.id(),
+            t.sender.clone(),
+            t.receiver.clone(),
+            t.amount.to_string(),
+            t.fee.to_string(),
+        ])
+    });
+    let widths = [
+        Constraint::Length(5),
+        Constraint::Ratio(1, 3),
+        Constraint::Ratio(1, 3),
+        Constraint::Ratio(1, 3),
+        Constraint::Length(16),
+        Constraint::Length(12),
+    ];
+    let title = match block.is_pruned() {
+        true => format!(" Transactions ({}, pruned) ", block.count()),
+        false => format!(" Transactions ({}) ", block.count()),
+    };
+    let table = Table::new(rows, widths)
+        .header(Row::new(["#", "Id", "From", "To", "Amount", "Fee"]).style(bold()))
+        .block(Panel::bordered().title(title))
+        .row_highlight_style(selected());
+    frame.render_stateful_widget(table, list, state);
+}
+
+fn draw_transaction(frame: &mut Frame, area: Rect, height: usize, block: &Block, index: usize) {
+    let [details, path] =
+        Layout::vertical([Constraint::Length(11), Constraint::Min(0)]).areas(area);
+    let transaction = &block.transactions[index];
+    let id = transaction.id();
+    let lines = vec![
+        field("Id", id.clone()),
+        field("Block", format!("{} ({})", height, block.hash())),
+        field("Version", transaction.version.to_string()),
+        field("From", transaction.sender.clone()),
+        field("To", transaction.receiver.clone()),
+        field("Amount", transaction.amount.to_string()),
+        field("Fee", transaction.fee.to_string()),
+        field("Nonce", transaction.nonce.to_string()),
+        field("Signature", transaction.signature.clone()),
+    ];
+    frame.render_widget(
+        Paragraph::new(lines).block(Panel::bordered().title(" Transaction ")),
+        details,
+    );
+
+    // The steps from the transaction's leaf up to the root in the header
+    let root = &block.header.merkle;
+    let leaf = transaction.leaf();
+    let mut lines = vec![field("Leaf", leaf.clone())];
+    let proof = block.merkle_proof(&id).unwrap_or_default();
+    for step in &proof {
+        let side = if step.left { "Left" } else { "Right" };
+        lines.push(field(side, step.hash.clone()));
+    }
+    lines.push(field("Root", root.clone()));
+    lines.push(Line::from(""));
+    lines.push(
+        match merkle::verify(&leaf, &proof, root, block.header.version) {
+            true => Line::from("Merkle proof verified"),
+            false => Line::from("Merkle proof verification failed"),
+        },
+    );
+    frame.render_widget(
+        Paragraph::new(lines).block(Panel::bordered().title(" Merkle path ")),
+        path,
+    );
+}
+
+fn draw_address(
+    frame: &mut Frame,
+    area: Rect,
+    chain: &Chain,
+    address: &str,
+    found: &[(usize, usize)],
+    state: &mut TableState,
+) {
+    let [details, list] = Layout::vertical([Constraint::Length(5), Constraint::Min(0)]).areas(area);
+    let lines = vec![
+        field("Address", address.to_string()),
+        field("Balance", chain.balance(address).to_string()),
+        field("Available", chain.available_balance(address).to_string()),
+    ];
+    frame.render_widget(
+        Paragraph::new(lines).block(Panel::bordered().title(" Address ")),
+        details,
+    );
+
+    let rows = found.iter().filter_map(|&(height, index)| {
+        let t = chain.chain.get(height)?.transactions.get(index)?;
+        let (direction, other) = match t.sender == address {
+            true => ("out", &t.receiver),
+            false => ("in", &t.sender),
+        };
+        Some(Row::new(vec![
+            height.to_string(),
+            t.id(),
+            direction.to_string(),
+            other.clone(),
+            t.amount.to_string(),
+            t.fee.to_string(),
+        ]))
+    });
+    let widths = [
+        Constraint::Length(7),
+        Constraint::Ratio(1, 2),
+        Constraint::Length(4),
+        Constraint::Ratio(1, 2),
+        Constraint::Length(16),
+        Constraint::Length(12),
+    ];
+    let table = Table::new(rows, widths)
+        .header(Row::new(["Block", "Id", "", "With", "Amount", "Fee"]).style(bold()))
+        .block(Panel::bordered().title(format!(" Transactions ({}) ", found.len())))
+        .row_highlight_style(selected());
+    frame.render_stateful_widget(table, list, state);
+}
+
+// Shown when the opened block is gone from the chain.
+fn draw_missing(frame: &mut Frame, area: Rect) {
+    frame.render_widget(
+        Paragraph::new("This block is no longer in the chain.").block(Panel::bordered()),
+        area,
+    );
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::blockchain::Block;
+
+// How blocks are written by `export` and read by `import`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // A JSON array of blocks
+    Json,
+    // One JSON block per line, like the block file of the storage
+    Jsonl,
+    // One row per transaction with the height and hash of its block, can't be imported
+    Csv,
+}
+
+#[derive(Debug)]
+pub struct ParseFormatError(String);
+
+impl fmt::Display for ParseFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown format {}, expected json, jsonl or csv", self.0)
+    }
+}
+
+impl std::error::Error for ParseFormatError {}
+
+impl FromStr for Format {
+    type Err = ParseFormatError;
+
+    fn from_str(input: &str) -> Result<Format, ParseFormatError> {
+        match input.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            _ => Err(ParseFormatError(input.to_string())),
+        }
+    }
+}
+
+const CSV_HEADER: &str =
+    "height,block_hash,timestamp,position,id,version,sender,receiver,amount,fee,nonce";
+
+// Writes `blocks`, the first of which is at height `start`.
+pub fn write<W: Write>(
+    out: &mut W,
+    blocks: &[Block],
+    start: usize,
+    format: Format,
+) -> io::Result<()> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, blocks)?;
+            writeln!(out)
+        }
+        Format::Jsonl => blocks.iter().try_for_each(|block| {
+            serde_json::to_writer(&mut *out, block)?;
+            writeln!(out)
+        }),
+        Format::Csv => {
+            writeln!(out, "{}", CSV_HEADER)?;
+            for (offset, block) in blocks.iter().enumerate() {
+                let hash = block.hash();
+                for (position, t) in block.transactions.iter().enumerate() {
+                    let fields = [
+                        (start + offset).to_string(),
+                        hash.clone(),
+                        block.header.timestamp.to_string(),
+                        position.to_string(),
+                        t.id(),
+                        t.version.to_string(),
+                        csv_field(&t.sender),
+                        csv_field(&t.receiver),
+                        t.amount.to_string(),
+                        t.fee.to_string(),
+                        t.nonce.to_string(),
+                    ];
+                    writeln!(out, "{}", fields.join(","))?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+// Reads blocks written by `write`.
+pub fn read(data: &str, format: Format) -> Result<Vec<Block>, String> {
+    match format {
+        Format::Json => serde_json::from_str(data).map_err(|e| e.to_string()),
+        Format::Jsonl => data
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line).map_err(|e| format!("line {}: {}", index + 1, e))
+            })
+            .collect(),
+        Format::Csv => {
+            Err("CSV only holds the transactions, import JSON or JSON lines".to_string())
+        }
+    }
+}
+
+// Quotes a field that would otherwise break the row.
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
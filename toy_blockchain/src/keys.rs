@@ -0,0 +1,54 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::CryptoRng;
+use rand::RngCore;
+
+pub fn generate_keypair<R: RngCore + CryptoRng>(rng: &mut R) -> SigningKey {
+    SigningKey::generate(rng)
+}
+
+// An address is the hex encoded public key, so signatures can be checked
+// against the sender address directly.
+pub fn address(key: &SigningKey) -> String {
+    hex::encode(key.verifying_key().as_bytes())
+}
+
+pub fn secret_to_hex(key: &SigningKey) -> String {
+    hex::encode(key.to_bytes())
+}
+
+pub fn secret_from_hex(secret: &str) -> Option<SigningKey> {
+    let bytes: [u8; 32] = hex::decode(secret.trim()).ok()?.try_into().ok()?;
+    Some(SigningKey::from_bytes(&bytes))
+}
+
+pub fn sign(key: &SigningKey, message: &[u8]) -> String {
+    hex::encode(key.sign(message).to_bytes())
+}
+
+// Only the lowercase hex that `address` and `sign` produce is accepted, and
+// the signature is checked strictly, so a signed message has exactly one
+// valid signature string. Transaction ids hash that string.
+pub fn verify(address: &str, message: &[u8], signature: &str) -> bool {
+    let key_bytes: [u8; 32] = match decode_canonical(address) {
+        Some(bytes) => bytes,
+        None => return false,
+    };
+    let key = match VerifyingKey::from_bytes(&key_bytes) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    let signature_bytes: [u8; 64] = match decode_canonical(signature) {
+        Some(bytes) => bytes,
+        None => return false,
+    };
+    key.verify_strict(message, &Signature::from_bytes(&signature_bytes))
+        .is_ok()
+}
+
+fn decode_canonical<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let bytes: [u8; N] = hex::decode(text).ok()?.try_into().ok()?;
+    match hex::encode(bytes) == text {
+        true => Some(bytes),
+        false => None,
+    }
+}
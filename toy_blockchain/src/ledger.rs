@@ -0,0 +1,95 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::amount::Amount;
+use crate::blockchain::{Transaction, TransactionError};
+
+pub const ROOT_ADDRESS: &str = "Root";
+
+// Account balances derived from the transactions in the chain. Transactions
+// sent by "Root" are block rewards and mint new coins. Fees leave the sender
+// here and reach the miner through the coinbase of the same block.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    balances: HashMap<String, Amount>,
+    transactions: HashSet<String>,
+}
+
+impl Ledger {
+    pub fn new() -> Ledger {
+        Ledger::default()
+    }
+
+    // The ledger at the height of a snapshot.
+    pub fn from_state(balances: HashMap<String, Amount>, transactions: HashSet<String>) -> Ledger {
+        Ledger {
+            balances,
+            transactions,
+        }
+    }
+
+    // Moves the amount and fee out of the sender's balance. The ledger is left
+    // unchanged when the sender can't pay or the receiver's balance would overflow.
+    pub fn apply_transaction(&mut self, transaction: &Transaction) -> Result<(), TransactionError> {
+        let cost = transaction.cost().ok_or(TransactionError::Overflow)?;
+        let minted = transaction.sender == ROOT_ADDRESS;
+
+        let available = self.balance(&transaction.sender);
+        let remaining = match minted {
+            true => available,
+            false => available
+                .checked_sub(cost)
+                .ok_or(TransactionError::InsufficientFunds {
+                    available,
+                    amount: cost,
+                })?,
+        };
+        let received = match transaction.receiver == transaction.sender {
+            true => remaining,
+            false => self.balance(&transaction.receiver),
+        }
+        .checked_add(transaction.amount)
+        .ok_or(TransactionError::Overflow)?;
+
+        if !minted {
+            self.balances.insert(transaction.sender.clone(), remaining);
+        }
+        self.balances.insert(transaction.receiver.clone(), received);
+        self.transactions.insert(transaction.id());
+        Ok(())
+    }
+
+    // Undoes `apply_transaction`, transactions have to be reverted in the
+    // opposite order they were applied in.
+    pub fn revert_transaction(&mut self, transaction: &Transaction) {
+        let amount = transaction.amount;
+        let received = self.balance(&transaction.receiver).checked_sub(amount);
+        self.balances.insert(
+            transaction.receiver.clone(),
+            received.expect("reverted a transaction that was not applied"),
+        );
+        if transaction.sender != ROOT_ADDRESS {
+            // Applied transactions can't overflow, so their cost is known
+            let cost = transaction.cost().unwrap();
+            let sent = self.balance(&transaction.sender).checked_add(cost);
+            self.balances
+                .insert(transaction.sender.clone(), sent.unwrap());
+        }
+        self.transactions.remove(&transaction.id());
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.transactions.contains(id)
+    }
+
+    pub fn balance(&self, address: &str) -> Amount {
+        self.balances.get(address).cloned().unwrap_or_default()
+    }
+
+    pub fn balances(&self) -> impl Iterator<Item = (&String, &Amount)> {
+        self.balances.iter()
+    }
+
+    pub fn transactions(&self) -> impl Iterator<Item = &String> {
+        self.transactions.iter()
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use num_bigint::BigUint;
+
+use crate::blockchain::{Blockheader, Chain, Params, TransactionProof, ValidationError};
+use crate::consensus::{self, Consensus};
+use crate::merkle;
+use crate::node::{self, Message};
+
+#[derive(Debug)]
+pub enum LightError {
+    Network(io::Error),
+    UnexpectedReply,
+    InvalidHeader(ValidationError),
+    UnknownHeight(usize),
+    InvalidProof,
+    Genesis { expected: String, found: String },
+}
+
+impl fmt::Display for LightError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LightError::Network(e) => write!(f, "{}", e),
+            LightError::UnexpectedReply => write!(f, "unexpected reply"),
+            LightError::InvalidHeader(e) => write!(f, "{}", e),
+            LightError::UnknownHeight(height) => {
+                write!(f, "no header at height {} was synced", height)
+            }
+            LightError::InvalidProof => {
+                write!(f, "the Merkle proof doesn't match the header")
+            }
+            LightError::Genesis { expected, found } => {
+                write!(f, "the genesis block is {} instead of {}", found, expected)
+            }
+        }
+    }
+}
+
+// Follows the chain of a node from its headers only. The headers are checked
+// like a full node checks them, linkage and seal included, so a node can't
+// hand out a header chain it didn't do the work for. Transactions are then
+// checked against the Merkle roots of the headers. The genesis block, whose
+// target no earlier block decides, is pinned by its hash, so a node can't
+// make up a chain with an easy target either.
+pub struct LightClient {
+    pub headers: Vec<Blockheader>,
+    genesis: String,
+    consensus: Arc<dyn Consensus>,
+    work: BigUint,
+}
+
+impl LightClient {
+    pub fn new(params: &Params, genesis: String) -> LightClient {
+        LightClient {
+            headers: Vec::new(),
+            genesis,
+            consensus: consensus::from_params(params),
+            work: BigUint::default(),
+        }
+    }
+
+    // Fetches the headers of `peer` and switches to them when they are valid
+    // and have more work. Returns whether the header chain changed.
+    pub fn sync(&mut self, peer: &str) -> Result<bool, LightError> {
+        let tip = self.headers.last().map(|header| header.hash());
+        let mut headers = self.fetch_headers(peer, self.headers.len())?;
+        let extends = match headers.first() {
+            Some(first) => Some(&first.previous_hash) == tip.as_ref(),
+            None => return Ok(false),
+        };
+        // The peer is on another branch, start over from its genesis block
+        let (mut chain, mut work) = match extends {
+            true => (self.headers.clone(), self.work.clone()),
+            false => {
+                headers = self.fetch_headers(peer, 0)?;
+                (Vec::new(), BigUint::default())
+            }
+        };
+        if let (true, Some(first)) = (chain.is_empty(), headers.first()) {
+            if first.hash() != self.genesis {
+                return Err(LightError::Genesis {
+                    expected: self.genesis.clone(),
+                    found: first.hash(),
+                });
+            }
+        }
+
+        for header in headers {
+            let res =
+                Chain::check_header(&header, chain.as_slice().into(), self.consensus.as_ref());
+            if let Err(reason) = res {
+                let index = chain.len();
+                return Err(LightError::InvalidHeader(ValidationError { index, reason }));
+            }
+            work += self.consensus.work(&header);
+            chain.push(header);
+        }
+        if work <= self.work {
+            return Ok(false);
+        }
+        self.headers = chain;
+        self.work = work;
+        Ok(true)
+    }
+
+    pub fn fetch_proof(
+        &self,
+        peer: &str,
+        id: &str,
+    ) -> Result<Option<TransactionProof>, LightError> {
+        let message = Message::GetProof { id: id.to_string() };
+        match node::request(peer, message).map_err(LightError::Network)? {
+            Message::Proof(proof) => Ok(proof),
+            _ => Err(LightError::UnexpectedReply),
+        }
+    }
+
+    // Checks that `proof` shows the transaction with `id` in the synced
+    // header chain, and returns its number of confirmations.
+    pub fn verify(&self, id: &str, proof: &TransactionProof) -> Result<usize, LightError> {
+        let header = self
+            .headers
+            .get(proof.height)
+            .ok_or(LightError::UnknownHeight(proof.height))?;
+        let leaf = proof.transaction.leaf();
+        if proof.transaction.id() != id
+            || !merkle::verify(&leaf, &proof.steps, &header.merkle, header.version)
+        {
+            return Err(LightError::InvalidProof);
+        }
+        Ok(self.headers.len() - proof.height)
+    }
+
+    fn fetch_headers(&self, peer: &str, from: usize) -> Result<Vec<Blockheader>, LightError> {
+        match node::request(peer, Message::GetHeaders { from }).map_err(LightError::Network)? {
+            Message::Headers(headers) => Ok(headers),
+            _ => Err(LightError::UnexpectedReply),
+        }
+    }
+}
@@ -1,125 +1,1155 @@
 #[macro_use]
 extern crate serde_derive;
 
-use std::io;
+use std::collections::BTreeMap;
+use std::fs;
 use std::io::Write;
-use std::process;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
+use clap::{Args, Parser, Subcommand};
+use ed25519_dalek::SigningKey;
+use rand::rngs::StdRng;
+
+mod amount;
+mod api;
 mod blockchain;
+mod blocktree;
+mod consensus;
+mod encoding;
+mod env;
+mod events;
+mod explorer;
+mod export;
+mod keys;
+mod ledger;
+mod light;
+mod mempool;
+mod menu;
+mod merkle;
+mod miner;
+mod node;
+mod pow;
+mod script;
+mod snapshot;
+mod storage;
+mod wallet;
+
+use amount::Amount;
+use blockchain::{Chain, Params, Transaction};
+use consensus::ConsensusParams;
+use env::{Env, FixedClock, QuietLogger};
+use export::Format;
+use light::LightClient;
+use script::{Condition, Witness};
+use snapshot::Snapshot;
+use storage::{Config, Storage};
+use wallet::Wallet;
+
+const DATA_DIR: &str = "chain_data";
+const WALLET_FILE: &str = "wallet.json";
+// Read instead of prompting for the wallet passphrase, e.g. in scripts
+const PASSPHRASE_VAR: &str = "TOY_BLOCKCHAIN_PASSPHRASE";
+// Read instead of prompting for the secret key of `tx`
+const KEY_VAR: &str = "TOY_BLOCKCHAIN_KEY";
+
+#[derive(Parser)]
+#[command(about = "A toy blockchain")]
+struct Cli {
+    /// Where the chain is stored
+    #[arg(long, global = true, default_value = DATA_DIR)]
+    data_dir: String,
+
+    /// Seed the random numbers of new keys and transaction nonces, only for reproducible tests
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Without a subcommand the interactive menu is started
+    #[command(flatten)]
+    menu: MenuArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start the interactive menu (the default)
+    Menu(MenuArgs),
+    /// Create a new chain and mine its genesis block
+    Init(InitArgs),
+    /// Generate a key pair
+    Keygen,
+    /// Sign a transaction and add it to the pending transactions
+    Tx(TxArgs),
+    /// Print the lock address of the conditions in a JSON file
+    LockAddress {
+        /// File with the conditions, e.g. {"After":{"height":100}}
+        conditions: String,
+    },
+    /// Spend coins from a lock address and add the transaction to the pending transactions
+    Spend(SpendArgs),
+    /// Mine blocks with the pending transactions
+    Mine {
+        /// Number of blocks to mine
+        #[arg(long, default_value_t = 1)]
+        count: u32,
+        /// Number of mining threads (default: one per CPU)
+        #[arg(long)]
+        threads: Option<usize>,
+        /// Only print the mined blocks, not the progress of the miner
+        #[arg(long)]
+        quiet: bool,
+    },
+    /// Print a block as JSON
+    ShowBlock {
+        /// Block height or block hash
+        block: String,
+    },
+    /// Print the balance of an address
+    Balance {
+        address: String,
+        /// Include the pending transactions
+        #[arg(long)]
+        pending: bool,
+    },
+    /// Serve the chain over an HTTP JSON API
+    Serve {
+        /// Address of the HTTP API
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        http: String,
+        /// Also run as a node listening on this address
+        #[arg(long)]
+        listen: Option<String>,
+        /// File with the peers to connect to in node mode
+        #[arg(long, requires = "listen")]
+        peers: Option<String>,
+        /// Number of mining threads (default: one per CPU)
+        #[arg(long)]
+        threads: Option<usize>,
+        /// Append the events of the chain to this file, one JSON object per line
+        #[arg(long)]
+        events: Option<String>,
+    },
+    /// Switch the encoding of new blocks, existing blocks keep their own
+    Migrate {
+        /// Encoding version of new blocks and transactions
+        #[arg(long, default_value_t = encoding::VERSION)]
+        encoding_version: u32,
+    },
+    /// Browse the chain in a terminal explorer
+    Explore {
+        /// Also run as a node listening on this address, so new blocks show up
+        #[arg(long)]
+        listen: Option<String>,
+        /// File with the peers to connect to in node mode
+        #[arg(long, requires = "listen")]
+        peers: Option<String>,
+    },
+    /// Check that a transaction is mined, syncing only block headers from nodes
+    Light {
+        /// Id of the transaction
+        id: String,
+        /// Node to sync from, can be repeated
+        #[arg(long, required = true)]
+        node: Vec<String>,
+        /// Confirmations the transaction needs, counting its own block
+        #[arg(long, default_value_t = 1)]
+        confirmations: usize,
+        /// Hash of the genesis block of the chain
+        #[arg(long)]
+        genesis: String,
+        #[command(flatten)]
+        params: ParamsArgs,
+    },
+    /// Validate the whole chain
+    Validate,
+    /// Write the chain, or a range of it, as JSON, JSON lines or a CSV of transactions
+    Export {
+        /// Output file, stdout if omitted
+        #[arg(long)]
+        output: Option<String>,
+        /// json, jsonl or csv
+        #[arg(long, default_value = "json")]
+        format: Format,
+        /// Height of the first block
+        #[arg(long, default_value_t = 0)]
+        from: usize,
+        /// Height of the last block, the tip if omitted
+        #[arg(long)]
+        to: Option<usize>,
+    },
+    /// Validate the blocks in a JSON or JSON lines file and add them to the chain
+    Import {
+        /// File written by export
+        input: String,
+        /// json or jsonl
+        #[arg(long, default_value = "json")]
+        format: Format,
+    },
+    /// Write the balances after a block and print their commitment
+    Snapshot {
+        /// Height of the block (default: the tip)
+        #[arg(long)]
+        height: Option<usize>,
+        /// File to write to (default: the snapshots directory of the chain)
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Drop the transactions of the blocks up to a stored snapshot
+    Prune {
+        /// Height of the snapshot (default: the latest one)
+        #[arg(long)]
+        height: Option<usize>,
+    },
+    /// Start the chain created by init from a snapshot and validate forward from it
+    Bootstrap {
+        /// File written by snapshot
+        input: String,
+        /// Commitment the snapshot is expected to have, e.g. as printed by a trusted node
+        #[arg(long)]
+        commitment: Option<String>,
+    },
+    /// Manage the accounts of the wallet and send coins from them
+    Wallet {
+        /// Wallet file (default: wallet.json in the data directory)
+        #[arg(long)]
+        file: Option<String>,
+        #[command(subcommand)]
+        command: WalletCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum WalletCommand {
+    /// Create an empty wallet protected by a passphrase
+    Create,
+    /// Generate a key pair for a new account
+    New {
+        /// Name of the account
+        name: String,
+    },
+    /// Add an existing secret key as a new account
+    Import {
+        /// Name of the account
+        name: String,
+        /// File holding the secret key
+        #[arg(long)]
+        key_file: String,
+    },
+    /// List the accounts with their balances
+    List,
+    /// Sign a transaction from an account and add it to the pending transactions
+    Send {
+        /// Account to send from
+        #[arg(long)]
+        from: String,
+        /// Account name or address of the receiver
+        #[arg(long)]
+        to: String,
+        /// Amount in coins, with up to 8 decimals
+        #[arg(long)]
+        amount: Amount,
+        /// Fee paid to the miner, higher fees per byte are mined first
+        #[arg(long, default_value = "0")]
+        fee: Amount,
+    },
+    /// Show the mined and pending transactions of the accounts
+    History {
+        /// Only show this account
+        account: Option<String>,
+    },
+}
+
+#[derive(Args)]
+struct InitArgs {
+    /// Address that receives the mining rewards, a new key pair is generated if omitted
+    #[arg(long)]
+    miner: Option<String>,
+    /// Leading zero bits, or compact bits like 0x1f00ffff
+    #[arg(long, value_parser = parse_difficulty)]
+    difficulty: u32,
+    /// Subsidy of the first blocks, halved every --halving-interval blocks
+    #[arg(long, default_value = "100")]
+    reward: Amount,
+    /// File with the secret key this node seals blocks with under --signers
+    #[arg(long)]
+    signer_key_file: Option<String>,
+    /// Store a snapshot every this many blocks, 0 to take none
+    #[arg(long, default_value_t = 0)]
+    snapshot_interval: u64,
+    /// Timestamp of the genesis block in milliseconds since the Unix epoch, so
+    /// chains created with the same settings share their genesis block
+    #[arg(long)]
+    genesis_time: Option<i64>,
+    #[command(flatten)]
+    params: ParamsArgs,
+}
+
+#[derive(Args)]
+struct TxArgs {
+    /// File holding the secret key of the sender, without it the key is taken
+    /// from TOY_BLOCKCHAIN_KEY or asked for
+    #[arg(long)]
+    key_file: Option<String>,
+    /// Address of the receiver
+    #[arg(long)]
+    to: String,
+    /// Amount in coins, with up to 8 decimals
+    #[arg(long)]
+    amount: Amount,
+    /// Fee paid to the miner, higher fees per byte are mined first
+    #[arg(long, default_value = "0")]
+    fee: Amount,
+    /// Print the signed transaction as JSON instead of adding it, e.g. to post it to the HTTP API
+    #[arg(long)]
+    print: bool,
+}
+
+#[derive(Args)]
+struct SpendArgs {
+    /// File with the conditions of the lock address to spend from
+    #[arg(
+        long,
+        required_unless_present = "transaction",
+        conflicts_with = "transaction",
+        requires_all = ["to", "amount"]
+    )]
+    conditions: Option<String>,
+    /// File with a spending transaction printed by --print, to add signatures to it
+    #[arg(long)]
+    transaction: Option<String>,
+    /// Address of the receiver
+    #[arg(long)]
+    to: Option<String>,
+    /// Amount in coins, with up to 8 decimals
+    #[arg(long)]
+    amount: Option<Amount>,
+    /// Fee paid to the miner
+    #[arg(long, default_value = "0")]
+    fee: Amount,
+    /// File holding the secret key of a signer, can be repeated
+    #[arg(long)]
+    key_file: Vec<String>,
+    /// Preimage revealed for a hash lock
+    #[arg(long)]
+    preimage: Option<String>,
+    /// Print the transaction as JSON instead of adding it, e.g. for other signers
+    #[arg(long)]
+    print: bool,
+}
+
+#[derive(Args)]
+struct MenuArgs {
+    /// Run as a node listening on this address
+    #[arg(long)]
+    listen: Option<String>,
+    /// File with the peers to connect to in node mode
+    #[arg(long, requires = "listen")]
+    peers: Option<String>,
+    /// Number of mining threads (default: one per CPU)
+    #[arg(long)]
+    threads: Option<usize>,
+    #[command(flatten)]
+    params: ParamsArgs,
+}
+
+// Consensus parameters, only used when a new chain is created or by light clients.
+#[derive(Args)]
+struct ParamsArgs {
+    /// Blocks between difficulty retargets, 0 to disable, at least 2 otherwise
+    #[arg(long, default_value_t = Params::default().retarget_interval, value_parser = parse_retarget_interval)]
+    retarget_interval: u64,
+    /// Block time the retargeting aims for, in seconds
+    #[arg(long, default_value_t = Params::default().target_block_time / 1000)]
+    target_block_time: i64,
+    /// Most transactions in a block, not counting the coinbase
+    #[arg(long, default_value_t = Params::default().max_block_transactions)]
+    max_block_transactions: usize,
+    /// Most bytes of transactions in a block, not counting the coinbase
+    #[arg(long, default_value_t = Params::default().max_block_size)]
+    max_block_size: usize,
+    /// Encoding version of new blocks, 0 hashes JSON like chains from before versioning
+    #[arg(long, default_value_t = encoding::VERSION, value_parser = parse_version)]
+    encoding_version: u32,
+    /// Blocks between halvings of the mining subsidy, 0 to keep it constant
+    #[arg(long, default_value_t = Params::default().halving_interval)]
+    halving_interval: u64,
+    /// Coins the subsidies add up to at most
+    #[arg(long, default_value_t = Params::default().max_supply)]
+    max_supply: Amount,
+    /// Comma separated addresses that seal blocks in turn, proof of authority
+    /// instead of proof of work
+    #[arg(long, value_delimiter = ',')]
+    signers: Vec<String>,
+}
+
+impl ParamsArgs {
+    fn params(&self) -> Params {
+        Params {
+            retarget_interval: self.retarget_interval,
+            target_block_time: self.target_block_time * 1000,
+            max_block_transactions: self.max_block_transactions,
+            max_block_size: self.max_block_size,
+            version: self.encoding_version,
+            // Taken from the reward once it is known
+            subsidy: None,
+            halving_interval: self.halving_interval,
+            max_supply: self.max_supply,
+            consensus: match self.signers.is_empty() {
+                true => ConsensusParams::ProofOfWork,
+                false => ConsensusParams::ProofOfAuthority {
+                    signers: self.signers.clone(),
+                },
+            },
+        }
+    }
+}
+
+fn parse_version(input: &str) -> Result<u32, String> {
+    match input.parse() {
+        Ok(version) if version <= encoding::VERSION => Ok(version),
+        _ => Err(format!("expected a version up to {}", encoding::VERSION)),
+    }
+}
+
+// An interval of 1 has no time between its first and last block to measure.
+fn parse_retarget_interval(input: &str) -> Result<u64, String> {
+    match input.parse() {
+        Ok(1) | Err(_) => Err("expected 0 or at least 2 blocks".to_string()),
+        Ok(interval) => Ok(interval),
+    }
+}
+
+fn parse_difficulty(input: &str) -> Result<u32, String> {
+    pow::parse_difficulty(input).ok_or_else(|| format!("invalid difficulty: {}", input))
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let data_dir = cli.data_dir.as_str();
+    let mut rng = env::rng(cli.seed);
+
+    let res = match cli.command.unwrap_or(Command::Menu(cli.menu)) {
+        Command::Menu(args) => run_menu(data_dir, args, &mut rng),
+        Command::Init(args) => init(data_dir, args, &mut rng),
+        Command::Keygen => {
+            print_keypair(&keys::generate_keypair(&mut rng));
+            Ok(())
+        }
+        Command::Tx(args) => new_transaction(data_dir, args, &mut rng),
+        Command::LockAddress { conditions } => read_conditions(&conditions).map(|conditions| {
+            println!("{}", conditions.address());
+        }),
+        Command::Spend(args) => spend(data_dir, args, &mut rng),
+        Command::Mine {
+            count,
+            threads,
+            quiet,
+        } => mine(data_dir, count, threads, quiet),
+        Command::ShowBlock { block } => show_block(data_dir, &block),
+        Command::Balance { address, pending } => open_chain(data_dir).map(|chain| match pending {
+            true => println!("{}", chain.available_balance(&address)),
+            false => println!("{}", chain.balance(&address)),
+        }),
+        Command::Serve {
+            http,
+            listen,
+            peers,
+            threads,
+            events,
+        } => serve(data_dir, &http, listen, peers, threads, events),
+        Command::Migrate { encoding_version } => migrate(data_dir, encoding_version),
+        Command::Explore { listen, peers } => explore(data_dir, listen, peers),
+        Command::Light {
+            id,
+            node,
+            confirmations,
+            genesis,
+            params,
+        } => light(&id, &node, confirmations, genesis, &params.params()),
+        Command::Validate => validate(data_dir),
+        Command::Export {
+            output,
+            format,
+            from,
+            to,
+        } => export(data_dir, output, format, from, to),
+        Command::Import { input, format } => import(data_dir, &input, format),
+        Command::Snapshot { height, output } => snapshot(data_dir, height, output),
+        Command::Prune { height } => prune(data_dir, height),
+        Command::Bootstrap { input, commitment } => bootstrap(data_dir, &input, commitment),
+        Command::Wallet { file, command } => {
+            let path = file.map_or_else(|| Path::new(data_dir).join(WALLET_FILE), PathBuf::from);
+            wallet(data_dir, &path, command, &mut rng)
+        }
+    };
+
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn load_peers(path: Option<String>) -> Result<Vec<String>, String> {
+    match path {
+        Some(path) => node::load_peers(&path)
+            .map_err(|e| format!("unable to read peers from {}: {}", path, e)),
+        None => Ok(Vec::new()),
+    }
+}
 
-fn main() {
-    let mut miner_address = String::new();
-    let mut difficulty = String::new();
-    let mut choice = String::new();
-    let mut reward = String::new();
-
-    println!("Enter miner address: ");
-    io::stdout().flush();
-    io::stdin().read_line(&mut miner_address);
-
-    println!("Enter difficulty: ");
-    io::stdout().flush();
-    io::stdin().read_line(&mut difficulty);
-    let difficulty: u32 = difficulty
-        .trim()
-        .parse::<u32>()
-        .expect("were expecting a number");
-    println!("Generating genisis block...");
-
-    let mut chain = blockchain::Chain::new(
-        miner_address.trim().to_string(),
-        reward.trim().parse::<f64>().unwrap_or(100.0),
-        difficulty,
+fn run_menu(data_dir: &str, args: MenuArgs, rng: &mut StdRng) -> Result<(), String> {
+    let peers = load_peers(args.peers)?;
+    let threads = args.threads.unwrap_or_else(miner::default_threads);
+    menu::run(
+        data_dir,
+        args.params.params(),
+        threads,
+        args.listen,
+        peers,
+        rng,
     );
+    Ok(())
+}
 
-    loop {
-        println!("Menu:");
-        println!("1. New transaction");
-        println!("2. Mine block");
-        println!("3. Change difficulty (current: {})", chain.difficulty);
-        println!("4. Change reward (current: {})", chain.reward);
-        println!("5. Exit");
-        print!("Enter choice: ");
-        io::stdout().flush();
-        choice.clear();
-        io::stdin().read_line(&mut choice);
-        println!("");
-
-        match choice.trim().parse().unwrap() {
-            1 => {
-                let mut sender = String::new();
-                let mut receiver = String::new();
-                let mut amount = String::new();
-
-                print!("Enter sender address: ");
-                io::stdout().flush();
-                io::stdin().read_line(&mut sender);
-
-                print!("Enter receiver address: ");
-                io::stdout().flush();
-                io::stdin().read_line(&mut receiver);
-
-                print!("Enter amount: ");
-                io::stdout().flush();
-                io::stdin().read_line(&mut amount);
-
-                let res = chain.new_transaction(
-                    sender.trim().to_string(),
-                    receiver.trim().to_string(),
-                    amount.trim().parse().unwrap(),
-                );
+// Reads the settings stored by `init`.
+fn load_config(data_dir: &str) -> Result<(Storage, Config), String> {
+    let storage =
+        Storage::open(data_dir).map_err(|e| format!("unable to open {}: {}", data_dir, e))?;
+    let config = storage
+        .load_config()
+        .map_err(|e| format!("unable to read the settings from {}: {}", data_dir, e))?
+        .ok_or_else(|| format!("no chain in {}, run init first", data_dir))?;
+    Ok((storage, config))
+}
+
+// Opens the chain created by `init`.
+fn open_chain(data_dir: &str) -> Result<Chain, String> {
+    open_chain_with(data_dir, Env::default())
+}
+
+fn open_chain_with(data_dir: &str, env: Env) -> Result<Chain, String> {
+    let (storage, config) = load_config(data_dir)?;
+    let signing_key = match &config.signer_key_file {
+        Some(path) => Some(read_secret_key(path)?),
+        None => None,
+    };
+    let mut chain = Chain::open(
+        storage,
+        config.miner_address,
+        config.reward,
+        config.bits,
+        config.params,
+        env,
+    )
+    .map_err(|e| format!("unable to load chain from {}: {}", data_dir, e))?;
+    chain.signing_key = signing_key;
+    chain.snapshot_interval = config.snapshot_interval;
+    Ok(chain)
+}
+
+// Takes a secret key from the environment, or asks for it without echoing it,
+// so it doesn't end up in the shell history or the process list.
+fn read_key() -> Result<String, String> {
+    if let Ok(key) = std::env::var(KEY_VAR) {
+        return Ok(key);
+    }
+    rpassword::prompt_password("Secret key: ").map_err(|e| format!("unable to read the key: {}", e))
+}
+
+fn read_secret_key(path: &str) -> Result<SigningKey, String> {
+    let secret = fs::read_to_string(path)
+        .map_err(|e| format!("unable to read the key from {}: {}", path, e))?;
+    keys::secret_from_hex(&secret).ok_or_else(|| format!("invalid secret key in {}", path))
+}
+
+fn init(data_dir: &str, args: InitArgs, rng: &mut StdRng) -> Result<(), String> {
+    let storage =
+        Storage::open(data_dir).map_err(|e| format!("unable to open {}: {}", data_dir, e))?;
+    match storage.load_config() {
+        Ok(None) => {}
+        Ok(Some(_)) => return Err(format!("a chain already exists in {}", data_dir)),
+        Err(e) => {
+            return Err(format!(
+                "unable to read the settings from {}: {}",
+                data_dir, e
+            ))
+        }
+    }
+
+    // The settings are read from elsewhere later on
+    let signer_key_file = match args.signer_key_file {
+        Some(path) => {
+            read_secret_key(&path)?;
+            let path = fs::canonicalize(&path)
+                .map_err(|e| format!("unable to resolve {}: {}", path, e))?;
+            Some(path.to_string_lossy().into_owned())
+        }
+        None => None,
+    };
+    let miner = args
+        .miner
+        .unwrap_or_else(|| print_keypair(&keys::generate_keypair(rng)));
+    let config = Config {
+        miner_address: miner,
+        reward: args.reward,
+        bits: args.difficulty,
+        params: Params {
+            subsidy: Some(args.reward),
+            ..args.params.params()
+        },
+        signer_key_file,
+        snapshot_interval: args.snapshot_interval,
+    };
+    storage
+        .save_config(&config)
+        .map_err(|e| format!("unable to store the settings: {}", e))?;
+
+    let env = match args.genesis_time {
+        Some(time) => Env {
+            clock: Arc::new(FixedClock::new(time, 0)),
+            ..Env::default()
+        },
+        None => Env::default(),
+    };
+    let chain = open_chain_with(data_dir, env)?;
+    println!("Created chain in {}", data_dir);
+    println!("Genesis block: {}", chain.chain[0].hash());
+    Ok(())
+}
+
+fn new_transaction(data_dir: &str, args: TxArgs, rng: &mut StdRng) -> Result<(), String> {
+    let key = match args.key_file {
+        Some(path) => read_secret_key(&path)?,
+        None => keys::secret_from_hex(&read_key()?).ok_or("invalid secret key")?,
+    };
+    // A printed transaction may be meant for a chain elsewhere
+    let version = match args.print && !Path::new(data_dir).exists() {
+        true => encoding::VERSION,
+        false => load_config(data_dir)?.1.params.version,
+    };
+    let transaction = Transaction::signed(rng, &key, args.to, args.amount, args.fee, version);
+    if args.print {
+        println!("{}", serde_json::to_string(&transaction).unwrap());
+        return Ok(());
+    }
+
+    let mut chain = open_chain(data_dir)?;
+    chain
+        .new_transaction(transaction.clone())
+        .map_err(|e| format!("transaction failed: {}", e))?;
+    println!("{}", transaction.id());
+    Ok(())
+}
+
+fn read_conditions(path: &str) -> Result<Condition, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("unable to read {}: {}", path, e))?;
+    let conditions: Condition = serde_json::from_str(&data)
+        .map_err(|e| format!("invalid conditions in {}: {}", path, e))?;
+    conditions
+        .check()
+        .map_err(|e| format!("invalid conditions in {}: {}", path, e))?;
+    Ok(conditions)
+}
+
+fn spend(data_dir: &str, args: SpendArgs, rng: &mut StdRng) -> Result<(), String> {
+    let mut transaction = match (args.conditions, args.transaction) {
+        (Some(path), _) => {
+            let witness = Witness {
+                conditions: read_conditions(&path)?,
+                signatures: BTreeMap::new(),
+                preimage: None,
+            };
+            let version = load_config(data_dir)?.1.params.version;
+            // clap requires both with --conditions
+            let (to, amount) = (args.to.unwrap(), args.amount.unwrap());
+            Transaction::spending(rng, witness, to, amount, args.fee, version)
+        }
+        (None, Some(path)) => {
+            let data =
+                fs::read_to_string(&path).map_err(|e| format!("unable to read {}: {}", path, e))?;
+            serde_json::from_str(&data)
+                .map_err(|e| format!("invalid transaction in {}: {}", path, e))?
+        }
+        (None, None) => unreachable!("clap requires --conditions or --transaction"),
+    };
+
+    let message = transaction.signing_bytes();
+    let witness = transaction
+        .witness
+        .as_mut()
+        .ok_or("the transaction doesn't spend from a lock address")?;
+    for path in &args.key_file {
+        let key = read_secret_key(path)?;
+        let signature = keys::sign(&key, &message);
+        witness.signatures.insert(keys::address(&key), signature);
+    }
+    if let Some(preimage) = args.preimage {
+        witness.preimage = Some(preimage);
+    }
+    if args.print {
+        println!("{}", serde_json::to_string(&transaction).unwrap());
+        return Ok(());
+    }
+
+    let mut chain = open_chain(data_dir)?;
+    chain
+        .new_transaction(transaction.clone())
+        .map_err(|e| format!("transaction failed: {}", e))?;
+    println!("{}", transaction.id());
+    Ok(())
+}
+
+fn mine(data_dir: &str, count: u32, threads: Option<usize>, quiet: bool) -> Result<(), String> {
+    let env = match quiet {
+        true => Env {
+            logger: Arc::new(QuietLogger),
+            ..Env::default()
+        },
+        false => Env::default(),
+    };
+    let mut chain = open_chain_with(data_dir, env)?;
+    if let Some(threads) = threads {
+        chain.miner_threads = threads;
+    }
+    for _ in 0..count {
+        if !chain.generate_new_block() {
+            return Err("block mining failed".to_string());
+        }
+        println!(
+            "Mined block {}: {}",
+            chain.chain.len() - 1,
+            chain.last_hash()
+        );
+    }
+    Ok(())
+}
+
+fn show_block(data_dir: &str, block: &str) -> Result<(), String> {
+    let chain = open_chain(data_dir)?;
+    let found = match block.parse::<usize>() {
+        Ok(height) => chain.chain.get(height),
+        Err(_) => chain.chain.iter().find(|b| b.hash() == block),
+    };
+    let found = found.ok_or_else(|| format!("block {} not found", block))?;
+    println!("{}", serde_json::to_string_pretty(found).unwrap());
+    Ok(())
+}
 
-                match res {
-                    true => println!("Transaction added!"),
-                    false => println!("Transaction failed!"),
+fn serve(
+    data_dir: &str,
+    http: &str,
+    listen: Option<String>,
+    peers: Option<String>,
+    threads: Option<usize>,
+    events: Option<String>,
+) -> Result<(), String> {
+    let peers = load_peers(peers)?;
+    let mut chain = open_chain(data_dir)?;
+    if let Some(threads) = threads {
+        chain.miner_threads = threads;
+    }
+    if let Some(path) = events {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("unable to open {}: {}", path, e))?;
+        let events = chain.subscribe();
+        thread::spawn(move || {
+            for event in events {
+                let line = serde_json::to_string(&event).unwrap();
+                if let Err(e) = writeln!(file, "{}", line) {
+                    eprintln!("Unable to write the event to {}: {}", path, e);
                 }
             }
-            2 => {
-                println!("Mining block...");
+        });
+    }
+    let chain = Arc::new(Mutex::new(chain));
 
-                let new_block = chain.generate_new_block();
-                match new_block {
-                    true => println!("Block mined!"),
-                    false => println!("Block mining failed!"),
-                }
+    let node = match listen {
+        Some(address) => Some(
+            node::Node::start(address, peers, chain.clone())
+                .map_err(|e| format!("unable to start node: {}", e))?,
+        ),
+        None => None,
+    };
+    api::Api::new(chain, node)
+        .serve(http)
+        .map_err(|e| format!("unable to serve on {}: {}", http, e))
+}
+
+// Existing blocks can't be re-encoded since their transactions are signed
+// in their own version, so only the blocks mined from now on change.
+fn migrate(data_dir: &str, version: u32) -> Result<(), String> {
+    if version > encoding::VERSION {
+        return Err(format!("unknown encoding version {}", version));
+    }
+    let chain = open_chain(data_dir)?;
+    let (storage, mut config) = load_config(data_dir)?;
+    let latest = chain.chain.last().map_or(0, |block| block.header.version);
+    if version < latest {
+        return Err(format!("blocks already use encoding version {}", latest));
+    }
+
+    config.params.version = version;
+    storage
+        .save_config(&config)
+        .map_err(|e| format!("unable to store the settings: {}", e))?;
+    println!("New blocks use encoding version {}", version);
+    Ok(())
+}
+
+fn explore(data_dir: &str, listen: Option<String>, peers: Option<String>) -> Result<(), String> {
+    let peers = load_peers(peers)?;
+    let chain = Arc::new(Mutex::new(open_chain(data_dir)?));
+    // The node only has to live as long as the explorer
+    let _node = match listen {
+        Some(address) => Some(
+            node::Node::start(address, peers, chain.clone())
+                .map_err(|e| format!("unable to start node: {}", e))?,
+        ),
+        None => None,
+    };
+    explorer::run(chain).map_err(|e| format!("unable to start the explorer: {}", e))
+}
+
+fn light(
+    id: &str,
+    nodes: &[String],
+    confirmations: usize,
+    genesis: String,
+    params: &Params,
+) -> Result<(), String> {
+    let mut client = LightClient::new(params, genesis);
+    for node in nodes {
+        match client.sync(node) {
+            Ok(true) => println!("Synced {} headers from {}", client.headers.len(), node),
+            Ok(false) => {}
+            Err(e) => eprintln!("Unable to sync with {}: {}", node, e),
+        }
+    }
+    let tip = client
+        .headers
+        .last()
+        .ok_or("no valid headers from any node")?;
+    println!(
+        "Header chain at height {}: {}",
+        client.headers.len() - 1,
+        tip.hash()
+    );
+
+    for node in nodes {
+        let proof = match client.fetch_proof(node, id) {
+            Ok(Some(proof)) => proof,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Unable to get a proof from {}: {}", node, e);
+                continue;
             }
-            3 => {
-                let mut new_difficulty = String::new();
-                print!("Enter new difficulty: ");
-                io::stdout().flush();
-                io::stdin().read_line(&mut new_difficulty);
-
-                let res = chain.update_difficulty(
-                    new_difficulty
-                        .trim()
-                        .parse::<u32>()
-                        .unwrap_or(chain.difficulty),
-                );
-                match res {
-                    true => println!("Difficulty updated!"),
-                    false => println!("Difficulty update failed!"),
-                }
+        };
+        let found = match client.verify(id, &proof) {
+            Ok(found) => found,
+            Err(e) => {
+                eprintln!("Invalid proof from {}: {}", node, e);
+                continue;
             }
-            4 => {
-                let mut new_reward = String::new();
-                print!("Enter new reward: ");
-                io::stdout().flush();
-                io::stdin().read_line(&mut new_reward);
-
-                let res =
-                    chain.update_reward(new_reward.trim().parse::<f64>().unwrap_or(chain.reward));
-                match res {
-                    true => println!("Reward updated!"),
-                    false => println!("Reward update failed!"),
-                }
+        };
+        let t = &proof.transaction;
+        println!(
+            "Transaction {} is in block {} with {} confirmations",
+            id, proof.height, found
+        );
+        println!(
+            "{} -> {}: {} (fee {})",
+            t.sender, t.receiver, t.amount, t.fee
+        );
+        if found < confirmations {
+            return Err(format!(
+                "{} confirmations, {} required",
+                found, confirmations
+            ));
+        }
+        return Ok(());
+    }
+    Err(format!("transaction {} is not in the chain", id))
+}
+
+fn validate(data_dir: &str) -> Result<(), String> {
+    let chain = open_chain(data_dir)?;
+    chain
+        .validate()
+        .map_err(|e| format!("chain is invalid: {}", e))?;
+    println!("Chain is valid ({} blocks)", chain.chain.len());
+    Ok(())
+}
+
+fn export(
+    data_dir: &str,
+    output: Option<String>,
+    format: Format,
+    from: usize,
+    to: Option<usize>,
+) -> Result<(), String> {
+    let chain = open_chain(data_dir)?;
+    let tip = chain.chain.len() - 1;
+    let to = to.unwrap_or(tip);
+    if from > to || to > tip {
+        return Err(format!(
+            "invalid range {}..={}, the chain ends at height {}",
+            from, to, tip
+        ));
+    }
+
+    let mut data = Vec::new();
+    export::write(&mut data, &chain.chain[from..=to], from, format).unwrap();
+    match output {
+        Some(path) => {
+            fs::write(&path, data).map_err(|e| format!("unable to write {}: {}", path, e))
+        }
+        None => {
+            print!("{}", String::from_utf8_lossy(&data));
+            Ok(())
+        }
+    }
+}
+
+fn import(data_dir: &str, input: &str, format: Format) -> Result<(), String> {
+    let data = fs::read_to_string(input).map_err(|e| format!("unable to read {}: {}", input, e))?;
+    let blocks =
+        export::read(&data, format).map_err(|e| format!("invalid blocks in {}: {}", input, e))?;
+    let count = blocks.len();
+
+    let mut chain = open_chain(data_dir)?;
+    let changed = chain
+        .import_blocks(blocks)
+        .map_err(|e| format!("import failed: {}", e))?;
+    match changed {
+        true => println!(
+            "Imported {} blocks, the chain is now at height {}",
+            count,
+            chain.chain.len() - 1
+        ),
+        false => println!(
+            "Imported {} blocks, the chain keeps its tip at height {}",
+            count,
+            chain.chain.len() - 1
+        ),
+    }
+    Ok(())
+}
+
+fn snapshot(data_dir: &str, height: Option<usize>, output: Option<String>) -> Result<(), String> {
+    let chain = open_chain(data_dir)?;
+    let tip = chain.chain.len() - 1;
+    let height = height.unwrap_or(tip);
+    let snapshot = chain
+        .snapshot(height)
+        .ok_or_else(|| match chain.pruned_height() {
+            Some(pruned) if height < pruned => {
+                format!("the chain is pruned up to height {}", pruned)
             }
-            5 => {
-                println!("Exiting...");
-                process::exit(0);
+            _ => format!("no block at height {}, the chain ends at {}", height, tip),
+        })?;
+
+    let path = match output {
+        Some(path) => {
+            let data = serde_json::to_string(&snapshot).unwrap();
+            fs::write(&path, data).map_err(|e| format!("unable to write {}: {}", path, e))?;
+            path
+        }
+        None => {
+            let (storage, _) = load_config(data_dir)?;
+            let path = storage
+                .save_snapshot(&snapshot)
+                .map_err(|e| format!("unable to store the snapshot: {}", e))?;
+            path.to_string_lossy().into_owned()
+        }
+    };
+    println!("Snapshot of block {} written to {}", snapshot.height, path);
+    println!("Commitment: {}", snapshot.commitment);
+    Ok(())
+}
+
+fn prune(data_dir: &str, height: Option<usize>) -> Result<(), String> {
+    let mut chain = open_chain(data_dir)?;
+    let (storage, _) = load_config(data_dir)?;
+    let height = match height {
+        Some(height) => height,
+        None => storage
+            .latest_snapshot_height()
+            .map_err(|e| format!("unable to read the snapshots: {}", e))?
+            .ok_or("no snapshot stored, take one first")?,
+    };
+    if let Some(pruned) = chain.pruned_height().filter(|pruned| height <= *pruned) {
+        return Err(format!(
+            "the chain is already pruned up to height {}",
+            pruned
+        ));
+    }
+    let snapshot = chain
+        .snapshot(height)
+        .ok_or_else(|| format!("no block at height {}", height))?;
+    chain
+        .prune(snapshot)
+        .map_err(|e| format!("unable to prune the chain: {}", e))?;
+    println!(
+        "Pruned the transactions of the blocks up to height {}",
+        height
+    );
+    Ok(())
+}
+
+fn bootstrap(data_dir: &str, input: &str, commitment: Option<String>) -> Result<(), String> {
+    let data = fs::read_to_string(input).map_err(|e| format!("unable to read {}: {}", input, e))?;
+    let snapshot: Snapshot =
+        serde_json::from_str(&data).map_err(|e| format!("invalid snapshot in {}: {}", input, e))?;
+    if commitment.is_some_and(|commitment| commitment != snapshot.commitment) {
+        return Err(format!(
+            "the snapshot has the commitment {}",
+            snapshot.commitment
+        ));
+    }
+
+    let (storage, config) = load_config(data_dir)?;
+    let chain = Chain::bootstrap(
+        storage,
+        snapshot,
+        config.miner_address,
+        config.reward,
+        config.bits,
+        config.params,
+        Env::default(),
+    )
+    .map_err(|e| format!("unable to bootstrap the chain: {}", e))?;
+    println!(
+        "Bootstrapped the chain at height {} from {}",
+        chain.chain.len() - 1,
+        input
+    );
+    Ok(())
+}
+
+fn wallet(
+    data_dir: &str,
+    path: &Path,
+    command: WalletCommand,
+    rng: &mut StdRng,
+) -> Result<(), String> {
+    if let WalletCommand::Create = command {
+        let passphrase = read_passphrase(true)?;
+        Wallet::create(path, &passphrase, rng)
+            .map_err(|e| format!("unable to create {}: {}", path.display(), e))?;
+        println!("Created wallet {}", path.display());
+        return Ok(());
+    }
+    let mut wallet = open_wallet(path)?;
+
+    match command {
+        WalletCommand::Create => unreachable!("handled above"),
+        WalletCommand::New { name } => {
+            let key = keys::generate_keypair(rng);
+            let account = wallet
+                .add_account(&name, &key, rng)
+                .map_err(|e| format!("unable to add {}: {}", name, e))?;
+            println!("{}", account.address);
+        }
+        WalletCommand::Import { name, key_file } => {
+            let key = read_secret_key(&key_file)?;
+            let account = wallet
+                .add_account(&name, &key, rng)
+                .map_err(|e| format!("unable to add {}: {}", name, e))?;
+            println!("{}", account.address);
+        }
+        WalletCommand::List => {
+            let chain = open_chain(data_dir)?;
+            for account in wallet.accounts() {
+                println!(
+                    "{}: {} balance {} (available: {})",
+                    account.name,
+                    account.address,
+                    chain.balance(&account.address),
+                    chain.available_balance(&account.address)
+                );
+            }
+        }
+        WalletCommand::Send {
+            from,
+            to,
+            amount,
+            fee,
+        } => {
+            let mut chain = open_chain(data_dir)?;
+            let version = chain.params.version;
+            let transaction = wallet
+                .transfer(rng, &from, &to, amount, fee, version)
+                .map_err(|e| format!("transaction failed: {}", e))?;
+            chain
+                .new_transaction(transaction.clone())
+                .map_err(|e| format!("transaction failed: {}", e))?;
+            println!("{}", transaction.id());
+        }
+        WalletCommand::History { account } => {
+            if let Some(name) = account
+                .as_deref()
+                .filter(|name| wallet.account(name).is_none())
+            {
+                return Err(format!("no account named {}", name));
             }
-            _ => {
-                println!("Invalid choice!");
+            let chain = open_chain(data_dir)?;
+            let entries = wallet
+                .history(&chain)
+                .into_iter()
+                .filter(|entry| account.as_ref().is_none_or(|name| entry.account == *name));
+            for entry in entries {
+                println!("{}", entry);
             }
         }
     }
+    Ok(())
+}
+
+fn open_wallet(path: &Path) -> Result<Wallet, String> {
+    if !path.exists() {
+        return Err(format!(
+            "no wallet at {}, run wallet create first",
+            path.display()
+        ));
+    }
+    let passphrase = read_passphrase(false)?;
+    Wallet::open(path, &passphrase).map_err(|e| format!("unable to open {}: {}", path.display(), e))
+}
+
+// Takes the passphrase from the environment, or asks for it without echoing it.
+fn read_passphrase(confirm: bool) -> Result<String, String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("Wallet passphrase: ")
+        .map_err(|e| format!("unable to read the passphrase: {}", e))?;
+    if confirm {
+        let again = rpassword::prompt_password("Repeat the passphrase: ")
+            .map_err(|e| format!("unable to read the passphrase: {}", e))?;
+        if again != passphrase {
+            return Err("the passphrases don't match".to_string());
+        }
+    }
+    Ok(passphrase)
+}
+
+fn print_new_keypair(rng: &mut StdRng) -> String {
+    print_keypair(&keys::generate_keypair(rng))
+}
+
+fn print_keypair(key: &SigningKey) -> String {
+    let address = keys::address(key);
+    println!("Address: {}", address);
+    println!("Secret key (keep it private): {}", keys::secret_to_hex(key));
+    address
 }
@@ -0,0 +1,85 @@
+use crate::blockchain::{Params, Transaction};
+use crate::ledger::Ledger;
+
+// Pending transactions waiting to be mined, in the order they arrived.
+#[derive(Debug, Clone, Default)]
+pub struct Mempool {
+    transactions: Vec<Transaction>,
+}
+
+impl Mempool {
+    pub fn new() -> Mempool {
+        Mempool::default()
+    }
+
+    pub fn from_transactions(transactions: Vec<Transaction>) -> Mempool {
+        Mempool { transactions }
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.transactions.iter().any(|t| t.id() == id)
+    }
+
+    pub fn push(&mut self, transaction: Transaction) {
+        self.transactions.push(transaction);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.transactions.retain(|t| t.id() != id);
+    }
+
+    // Picks the transactions for the next block on top of `ledger`, highest
+    // fee rate first, within the block limits of `params`. Only transactions of
+    // the block's encoding version that may go into the block at `height` are
+    // considered. A transaction that can't be paid for yet is retried once the
+    // others are applied, since it may spend coins received from one of them.
+    pub fn select(&self, ledger: &Ledger, params: &Params, height: u64) -> Vec<Transaction> {
+        let mut candidates: Vec<&Transaction> = self
+            .transactions
+            .iter()
+            .filter(|t| t.version == params.version && t.authorize(height).is_ok())
+            .collect();
+        candidates.sort_by(|a, b| b.fee_rate().total_cmp(&a.fee_rate()));
+
+        let mut ledger = ledger.clone();
+        let mut selected = Vec::new();
+        let mut size = 0;
+        loop {
+            let before = selected.len();
+            candidates.retain(|transaction| {
+                if selected.len() >= params.max_block_transactions
+                    || size + transaction.size() > params.max_block_size
+                    || ledger.apply_transaction(transaction).is_err()
+                {
+                    return true;
+                }
+                size += transaction.size();
+                selected.push((*transaction).clone());
+                false
+            });
+            if selected.len() == before {
+                return selected;
+            }
+        }
+    }
+
+    // Drops transactions that were mined, are no longer valid on top of
+    // `ledger` at `height` or use another encoding version than new blocks.
+    pub fn refresh(&mut self, ledger: &Ledger, version: u32, height: u64) {
+        let mut ledger = ledger.clone();
+        self.transactions.retain(|transaction| {
+            transaction.version == version
+                && transaction.authorize(height).is_ok()
+                && !ledger.contains(&transaction.id())
+                && ledger.apply_transaction(transaction).is_ok()
+        });
+    }
+}
@@ -0,0 +1,526 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use ed25519_dalek::SigningKey;
+use rand::rngs::StdRng;
+
+use crate::amount::Amount;
+use crate::blockchain::{BlockStatus, Chain, Params, Transaction};
+use crate::consensus::{ConsensusParams, SealError};
+use crate::env::Env;
+use crate::explorer;
+use crate::keys;
+use crate::merkle;
+use crate::node;
+use crate::pow;
+use crate::storage::{Config, Storage};
+use crate::wallet::Wallet;
+
+// Set while the menu is mining, so Ctrl-C cancels mining instead of exiting.
+static MINING: AtomicBool = AtomicBool::new(false);
+
+// Asks for the settings of a new chain.
+fn prompt_config(params: Params, rng: &mut StdRng) -> Config {
+    let mut miner_address = String::new();
+    let mut difficulty = String::new();
+    let mut reward = String::new();
+
+    println!("Enter miner address (leave empty to generate a key pair): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut miner_address).unwrap();
+    if miner_address.trim().is_empty() {
+        miner_address = crate::print_new_keypair(rng);
+    }
+
+    println!("Enter difficulty (leading zero bits, or compact bits like 0x1f00ffff): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut difficulty).unwrap();
+    let bits = pow::parse_difficulty(&difficulty).unwrap_or_else(|| {
+        eprintln!("Invalid difficulty: {}", difficulty.trim());
+        process::exit(1);
+    });
+
+    println!("Enter the subsidy of the first blocks (default 100): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut reward).unwrap();
+    let reward = match reward.trim() {
+        "" => Amount::from_coins(100).unwrap(),
+        reward => reward.parse().unwrap_or_else(|e| {
+            eprintln!("Invalid reward: {}", e);
+            process::exit(1);
+        }),
+    };
+
+    let mut signer_key_file = None;
+    if let ConsensusParams::ProofOfAuthority { .. } = params.consensus {
+        let mut path = String::new();
+        println!("Enter the file with the signer secret key (leave empty to not seal blocks): ");
+        io::stdout().flush().unwrap();
+        io::stdin().read_line(&mut path).unwrap();
+        if !path.trim().is_empty() {
+            let path = fs::canonicalize(path.trim()).unwrap_or_else(|e| {
+                eprintln!("Unable to resolve {}: {}", path.trim(), e);
+                process::exit(1);
+            });
+            signer_key_file = Some(path.to_string_lossy().into_owned());
+        }
+    }
+
+    Config {
+        miner_address: miner_address.trim().to_string(),
+        reward,
+        bits,
+        params: Params {
+            subsidy: Some(reward),
+            ..params
+        },
+        signer_key_file,
+        snapshot_interval: 0,
+    }
+}
+
+// Loads the chain in `data_dir`, asking for its settings on the first run.
+// Falls back to an in-memory chain when the directory can't be used.
+fn load_chain(data_dir: &str, params: Params, rng: &mut StdRng) -> Chain {
+    let storage = match Storage::open(data_dir) {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("Unable to open {}: {}", data_dir, e);
+            let config = prompt_config(params, rng);
+            let signing_key = load_signing_key(&config);
+            println!("Running without persistence, generating genisis block...");
+            let mut chain = Chain::new(
+                config.miner_address,
+                config.reward,
+                config.bits,
+                config.params,
+                Env::default(),
+            );
+            chain.signing_key = signing_key;
+            return chain;
+        }
+    };
+
+    let config = match storage.load_config() {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            let config = prompt_config(params, rng);
+            if let Err(e) = storage.save_config(&config) {
+                eprintln!("Unable to store the settings: {}", e);
+                process::exit(1);
+            }
+            config
+        }
+        Err(e) => {
+            eprintln!("Unable to read the settings from {}: {}", data_dir, e);
+            process::exit(1);
+        }
+    };
+
+    let signing_key = load_signing_key(&config);
+    println!("Loading chain from {}...", data_dir);
+    let mut chain = Chain::open(
+        storage,
+        config.miner_address,
+        config.reward,
+        config.bits,
+        config.params,
+        Env::default(),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("Unable to load chain from {}: {}", data_dir, e);
+        process::exit(1);
+    });
+    println!("Loaded {} blocks", chain.chain.len());
+    chain.signing_key = signing_key;
+    chain
+}
+
+fn load_signing_key(config: &Config) -> Option<SigningKey> {
+    config.signer_key_file.as_ref().map(|path| {
+        crate::read_secret_key(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    })
+}
+
+// Opens the wallet in the data directory the first time it is needed.
+fn unlock_wallet<'a>(wallet: &'a mut Option<Wallet>, path: &Path) -> Option<&'a Wallet> {
+    if wallet.is_none() && path.exists() {
+        let res = crate::read_passphrase(false)
+            .and_then(|passphrase| Wallet::open(path, &passphrase).map_err(|e| e.to_string()));
+        match res {
+            Ok(opened) => *wallet = Some(opened),
+            Err(e) => println!("Unable to open the wallet: {}", e),
+        }
+    }
+    wallet.as_ref()
+}
+
+pub fn run(
+    data_dir: &str,
+    params: Params,
+    threads: usize,
+    listen: Option<String>,
+    peers: Vec<String>,
+    rng: &mut StdRng,
+) {
+    let mut choice = String::new();
+    let wallet_path = Path::new(data_dir).join(crate::WALLET_FILE);
+    let mut wallet = None;
+    let mut chain = load_chain(data_dir, params, rng);
+    chain.miner_threads = threads;
+    let chain = Arc::new(Mutex::new(chain));
+
+    let cancel = chain.lock().unwrap().mining_cancel_flag();
+    ctrlc::set_handler(move || {
+        if MINING.load(Ordering::Relaxed) {
+            cancel.store(true, Ordering::Relaxed);
+        } else {
+            process::exit(130);
+        }
+    })
+    .expect("Unable to set the Ctrl-C handler");
+
+    let node = listen.map(|address| {
+        node::Node::start(address, peers, chain.clone()).unwrap_or_else(|e| {
+            eprintln!("Unable to start node: {}", e);
+            process::exit(1);
+        })
+    });
+
+    loop {
+        let (bits, reward, retargeting, scheduled, pending) = {
+            let chain = chain.lock().unwrap();
+            (
+                chain.next_bits(),
+                chain.next_reward(),
+                chain.params.retarget_interval > 0,
+                chain.params.subsidy.is_some(),
+                chain.mempool.len(),
+            )
+        };
+        println!("Menu:");
+        println!("1. New transaction");
+        println!("2. Mine block ({} pending transactions)", pending);
+        println!(
+            "3. Change difficulty (current: {:.2} zero bits, target {:#010x}{})",
+            pow::zero_bits(bits),
+            bits,
+            if retargeting { ", automatic" } else { "" }
+        );
+        println!(
+            "4. Change reward (current: {}{})",
+            reward,
+            if scheduled { ", halving schedule" } else { "" }
+        );
+        println!("5. Validate chain");
+        println!("6. Show balance");
+        println!("7. Generate key pair");
+        println!("8. Prove transaction inclusion");
+        println!("9. Explore chain");
+        println!("10. Wallet");
+        println!("11. Exit");
+        print!("Enter choice: ");
+        io::stdout().flush().unwrap();
+        choice.clear();
+        if io::stdin().read_line(&mut choice).unwrap() == 0 {
+            // Without input a node keeps serving its peers
+            if node.is_some() {
+                println!("No more input, serving peers...");
+                loop {
+                    thread::park();
+                }
+            }
+            process::exit(0);
+        }
+        println!();
+
+        match choice.trim().parse().unwrap_or(0) {
+            1 => {
+                let mut sender = String::new();
+                let mut receiver = String::new();
+                let mut amount = String::new();
+                let mut fee = String::new();
+
+                let wallet = unlock_wallet(&mut wallet, &wallet_path);
+                match wallet {
+                    Some(_) => print!("Enter sender account or secret key: "),
+                    None => print!("Enter sender secret key: "),
+                }
+                io::stdout().flush().unwrap();
+                io::stdin().read_line(&mut sender).unwrap();
+                let account = wallet.filter(|wallet| wallet.account(sender.trim()).is_some());
+                let key = match (account, keys::secret_from_hex(&sender)) {
+                    (Some(wallet), _) => match wallet.signing_key(sender.trim()) {
+                        Ok(key) => key,
+                        Err(e) => {
+                            println!("Unable to read the key: {}", e);
+                            continue;
+                        }
+                    },
+                    (None, Some(key)) => key,
+                    (None, None) => {
+                        println!("Invalid secret key!");
+                        continue;
+                    }
+                };
+
+                match wallet {
+                    Some(_) => print!("Enter receiver account or address: "),
+                    None => print!("Enter receiver address: "),
+                }
+                io::stdout().flush().unwrap();
+                io::stdin().read_line(&mut receiver).unwrap();
+                let receiver = match wallet {
+                    Some(wallet) => wallet.resolve(receiver.trim()),
+                    None => receiver.trim().to_string(),
+                };
+
+                print!("Enter amount: ");
+                io::stdout().flush().unwrap();
+                io::stdin().read_line(&mut amount).unwrap();
+
+                print!("Enter fee (default 0): ");
+                io::stdout().flush().unwrap();
+                io::stdin().read_line(&mut fee).unwrap();
+
+                let (amount, fee) = match (amount.trim().parse(), fee.trim()) {
+                    (Ok(amount), "") => (amount, Amount::ZERO),
+                    (Ok(amount), fee) => match fee.parse() {
+                        Ok(fee) => (amount, fee),
+                        Err(e) => {
+                            println!("Invalid fee: {}", e);
+                            continue;
+                        }
+                    },
+                    (Err(e), _) => {
+                        println!("Invalid amount: {}", e);
+                        continue;
+                    }
+                };
+                let version = chain.lock().unwrap().params.version;
+                let transaction = Transaction::signed(rng, &key, receiver, amount, fee, version);
+                let res = chain.lock().unwrap().new_transaction(transaction.clone());
+
+                match res {
+                    Ok(()) => {
+                        println!("Transaction added! Id: {}", transaction.id());
+                        if let Some(node) = &node {
+                            node.broadcast_transaction(transaction);
+                        }
+                    }
+                    Err(e) => println!("Transaction failed: {}", e),
+                }
+            }
+            2 => {
+                println!("Mining block... (press Ctrl-C to cancel)");
+
+                // The chain stays unlocked while mining, so blocks from peers can
+                // still arrive and cancel a stale candidate.
+                let (mut block, sealer) = chain.lock().unwrap().prepare_mining();
+                MINING.store(true, Ordering::Relaxed);
+                let res = sealer.seal(&mut block.header);
+                MINING.store(false, Ordering::Relaxed);
+                match res {
+                    Ok(()) => {}
+                    Err(SealError::Cancelled) => {
+                        println!("Block mining cancelled!");
+                        continue;
+                    }
+                    Err(e) => {
+                        println!("Unable to seal the block: {}", e);
+                        continue;
+                    }
+                }
+
+                println!("New Block Forged: {:#?}", &block);
+                let res = chain.lock().unwrap().add_forged_block(block.clone());
+                match res {
+                    Ok(status) => {
+                        match status {
+                            BlockStatus::Side => println!("Block mined on a side branch!"),
+                            _ => println!("Block mined!"),
+                        }
+                        if let Some(node) = &node {
+                            node.broadcast_block(block);
+                        }
+                    }
+                    Err(e) => println!("Block mining failed: {}", e),
+                }
+            }
+            3 if retargeting => {
+                println!("Difficulty is retargeted automatically!");
+            }
+            3 => {
+                let mut new_difficulty = String::new();
+                print!("Enter new difficulty (leading zero bits or compact bits): ");
+                io::stdout().flush().unwrap();
+                io::stdin().read_line(&mut new_difficulty).unwrap();
+
+                let res = chain
+                    .lock()
+                    .unwrap()
+                    .update_difficulty(pow::parse_difficulty(&new_difficulty).unwrap_or(bits));
+                match res {
+                    true => println!("Difficulty updated!"),
+                    false => println!("Difficulty update failed!"),
+                }
+            }
+            4 if scheduled => {
+                println!("Reward follows the halving schedule!");
+            }
+            4 => {
+                let mut new_reward = String::new();
+                print!("Enter new reward: ");
+                io::stdout().flush().unwrap();
+                io::stdin().read_line(&mut new_reward).unwrap();
+
+                let new_reward = match new_reward.trim().parse() {
+                    Ok(new_reward) => new_reward,
+                    Err(e) => {
+                        println!("Invalid reward: {}", e);
+                        continue;
+                    }
+                };
+                let res = chain.lock().unwrap().update_reward(new_reward);
+                match res {
+                    true => println!("Reward updated!"),
+                    false => println!("Reward update failed!"),
+                }
+            }
+            5 => {
+                let chain = chain.lock().unwrap();
+                match chain.validate() {
+                    Ok(()) => println!("Chain is valid ({} blocks)", chain.chain.len()),
+                    Err(e) => println!("Chain is invalid: {}", e),
+                }
+            }
+            6 => {
+                let mut address = String::new();
+                let wallet = unlock_wallet(&mut wallet, &wallet_path);
+                match wallet {
+                    Some(_) => print!("Enter account or address: "),
+                    None => print!("Enter address: "),
+                }
+                io::stdout().flush().unwrap();
+                io::stdin().read_line(&mut address).unwrap();
+
+                let address = match wallet {
+                    Some(wallet) => wallet.resolve(address.trim()),
+                    None => address.trim().to_string(),
+                };
+                let address = address.as_str();
+                let chain = chain.lock().unwrap();
+                println!(
+                    "Balance of {}: {} (available: {})",
+                    address,
+                    chain.balance(address),
+                    chain.available_balance(address)
+                );
+            }
+            7 => {
+                crate::print_new_keypair(rng);
+            }
+            8 => {
+                let mut id = String::new();
+                print!("Enter transaction id: ");
+                io::stdout().flush().unwrap();
+                io::stdin().read_line(&mut id).unwrap();
+
+                let id = id.trim();
+                let chain = chain.lock().unwrap();
+                let proof = match chain.transaction_proof(id) {
+                    Some(proof) => proof,
+                    None => {
+                        println!("Transaction not found in the chain!");
+                        continue;
+                    }
+                };
+                let header = &chain.chain[proof.height].header;
+                println!(
+                    "Found in block {} with merkle root {}",
+                    proof.height, header.merkle
+                );
+                for step in &proof.steps {
+                    let side = if step.left { "left" } else { "right" };
+                    println!("  {} {}", side, step.hash);
+                }
+                let leaf = proof.transaction.leaf();
+                match merkle::verify(&leaf, &proof.steps, &header.merkle, header.version) {
+                    true => println!("Merkle proof verified!"),
+                    false => println!("Merkle proof verification failed!"),
+                }
+            }
+            9 => {
+                if let Err(e) = explorer::run(chain.clone()) {
+                    println!("Unable to start the explorer: {}", e);
+                }
+            }
+            10 => {
+                if !wallet_path.exists() {
+                    println!("Creating a wallet in {}", wallet_path.display());
+                    let res = crate::read_passphrase(true).and_then(|passphrase| {
+                        Wallet::create(&wallet_path, &passphrase, rng).map_err(|e| e.to_string())
+                    });
+                    match res {
+                        Ok(created) => wallet = Some(created),
+                        Err(e) => {
+                            println!("Unable to create the wallet: {}", e);
+                            continue;
+                        }
+                    }
+                }
+                let Some(opened) = unlock_wallet(&mut wallet, &wallet_path) else {
+                    continue;
+                };
+
+                {
+                    let chain = chain.lock().unwrap();
+                    println!("Accounts:");
+                    for account in opened.accounts() {
+                        println!(
+                            "  {}: {} balance {} (available: {})",
+                            account.name,
+                            account.address,
+                            chain.balance(&account.address),
+                            chain.available_balance(&account.address)
+                        );
+                    }
+                    println!("History:");
+                    for entry in opened.history(&chain) {
+                        println!("  {}", entry);
+                    }
+                }
+
+                let mut name = String::new();
+                print!("Enter a name for a new account (leave empty to go back): ");
+                io::stdout().flush().unwrap();
+                io::stdin().read_line(&mut name).unwrap();
+                let name = name.trim();
+                if name.is_empty() {
+                    continue;
+                }
+                let key = keys::generate_keypair(rng);
+                match wallet.as_mut().unwrap().add_account(name, &key, rng) {
+                    Ok(account) => println!("Account {} created: {}", name, account.address),
+                    Err(e) => println!("Unable to add the account: {}", e),
+                }
+            }
+            11 => {
+                println!("Exiting...");
+                process::exit(0);
+            }
+            _ => {
+                println!("Invalid choice!");
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+
+use crate::blockchain::Chain;
+use crate::encoding;
+
+// From encoding version 1 on the tree is built level by level. A level with
+// an odd number of hashes pairs its last hash with itself, on every level
+// including the leaves, and a pair is hashed from the raw digests.
+//
+// Version 0 keeps the tree blocks were mined with before proofs existed, so
+// their roots still match: the last leaf is repeated when the number of
+// leaves is even, then the two hashes at the front of a queue are combined
+// and the result is put at its back until one hash is left. A pair is hashed
+// from the JSON string of the two hex strings joined together.
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProofStep {
+    pub hash: String,
+    // Whether `hash` is the left input when combined with the running hash
+    pub left: bool,
+}
+
+fn hash_pair(left: &str, right: &str, version: u32) -> String {
+    if encoding::is_legacy(&version) {
+        let mut joined = left.to_string();
+        joined.push_str(right);
+        return Chain::legacy_hash(&joined);
+    }
+    // A malformed hash can't decode and is hashed as empty, so it never verifies
+    let mut bytes = hex::decode(left).unwrap_or_default();
+    bytes.extend(hex::decode(right).unwrap_or_default());
+    Chain::hex_to_string(&Chain::digest(&bytes))
+}
+
+fn next_level(level: &[String], version: u32) -> Vec<String> {
+    level
+        .chunks(2)
+        .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0]), version))
+        .collect()
+}
+
+pub fn root(leaves: &[String], version: u32) -> Option<String> {
+    if encoding::is_legacy(&version) {
+        return legacy_tree(leaves, None).map(|(root, _)| root);
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level, version);
+    }
+    level.pop()
+}
+
+// The sibling hashes from the leaf at `index` up to the root.
+pub fn proof(leaves: &[String], mut index: usize, version: u32) -> Option<Vec<ProofStep>> {
+    if index >= leaves.len() {
+        return None;
+    }
+    if encoding::is_legacy(&version) {
+        return legacy_tree(leaves, Some(index)).map(|(_, steps)| steps);
+    }
+
+    let mut steps = Vec::new();
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        let left = index % 2 == 1;
+        let sibling = if left {
+            &level[index - 1]
+        } else {
+            level.get(index + 1).unwrap_or(&level[index])
+        };
+        steps.push(ProofStep {
+            hash: sibling.clone(),
+            left,
+        });
+        level = next_level(&level, version);
+        index /= 2;
+    }
+    Some(steps)
+}
+
+// The version 0 root, and the proof of the leaf at `index` if one is asked for.
+fn legacy_tree(leaves: &[String], index: Option<usize>) -> Option<(String, Vec<ProofStep>)> {
+    let mut queue: VecDeque<(String, bool)> = leaves
+        .iter()
+        .enumerate()
+        .map(|(i, leaf)| (leaf.clone(), Some(i) == index))
+        .collect();
+    if queue.len().is_multiple_of(2) {
+        // The copy is a different leaf, the proof follows the original
+        let (last, _) = queue.back()?.clone();
+        queue.push_back((last, false));
+    }
+
+    let mut steps = Vec::new();
+    while queue.len() > 1 {
+        let (left, left_proven) = queue.pop_front().unwrap();
+        let (right, right_proven) = queue.pop_front().unwrap();
+        if left_proven {
+            steps.push(ProofStep {
+                hash: right.clone(),
+                left: false,
+            });
+        } else if right_proven {
+            steps.push(ProofStep {
+                hash: left.clone(),
+                left: true,
+            });
+        }
+        let hash = hash_pair(&left, &right, 0);
+        queue.push_back((hash, left_proven || right_proven));
+    }
+    queue.pop_front().map(|(root, _)| (root, steps))
+}
+
+pub fn verify(leaf: &str, proof: &[ProofStep], root: &str, version: u32) -> bool {
+    let hash = proof.iter().fold(leaf.to_string(), |hash, step| {
+        if step.left {
+            hash_pair(&step.hash, &hash, version)
+        } else {
+            hash_pair(&hash, &step.hash, version)
+        }
+    });
+    hash == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn leaves(count: usize, version: u32) -> Vec<String> {
+        (0..count)
+            .map(|i| match encoding::is_legacy(&version) {
+                true => Chain::legacy_hash(&i.to_string()),
+                false => Chain::hex_to_string(&Chain::digest(i.to_string().as_bytes())),
+            })
+            .collect()
+    }
+
+    // Roots of the tree blocks were mined with before proofs existed
+    #[test]
+    fn legacy_root_matches_original_tree() {
+        let roots = [
+            "5a4cae46bde923931be72584bb87169345eaf701e6f2060c7b4f2ba1f139a",
+            "ac2cc3477b8590b858ef61d6fe34acd403f18376a94963086afbadad7ca876d",
+            "8fb1cfe062662a6c9f8e8ddececec4705dfbc4e59535f9d3e744a9bc9778419",
+            "59afe7c0e0f584c5e7ebe111d98a6c799c8f7fdddf4b96ddd4de95a7bbbcc5",
+        ];
+        for (count, expected) in (2..).zip(roots) {
+            assert_eq!(root(&leaves(count, 0), 0).unwrap(), expected);
+        }
+        assert_eq!(root(&leaves(1, 0), 0).unwrap(), leaves(1, 0)[0]);
+        assert_eq!(root(&[], 0), None);
+    }
+
+    #[test]
+    fn proofs_verify() {
+        for version in 0..=encoding::VERSION {
+            for count in 1..=9 {
+                let leaves = leaves(count, version);
+                let root = root(&leaves, version).unwrap();
+                for (index, leaf) in leaves.iter().enumerate() {
+                    let steps = proof(&leaves, index, version).unwrap();
+                    assert!(verify(leaf, &steps, &root, version));
+                    let other = &leaves[(index + 1) % count];
+                    assert_eq!(verify(other, &steps, &root, version), other == leaf);
+                }
+                assert!(proof(&leaves, count, version).is_none());
+            }
+        }
+    }
+
+    proptest! {
+        // Any set of leaves, including duplicates and odd levels
+        #[test]
+        fn every_leaf_proves_and_changes_the_root(
+            leaves in prop::collection::vec("[0-9a-f]{64}", 1..40),
+            version in 0..=encoding::VERSION,
+            changed in any::<prop::sample::Index>(),
+        ) {
+            let root = root(&leaves, version).unwrap();
+            for (index, leaf) in leaves.iter().enumerate() {
+                let steps = proof(&leaves, index, version).unwrap();
+                prop_assert!(verify(leaf, &steps, &root, version));
+            }
+
+            let index = changed.index(leaves.len());
+            let mut other = leaves.clone();
+            other[index] = Chain::hex_to_string(&Chain::digest(leaves[index].as_bytes()));
+            prop_assert_ne!(super::root(&other, version).unwrap(), root);
+        }
+    }
+}
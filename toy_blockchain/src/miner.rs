@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::blockchain::Blockheader;
+use crate::pow;
+
+pub struct MiningStats {
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl MiningStats {
+    pub fn hashrate(&self) -> f64 {
+        self.hashes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+// Searches for a nonce that makes `header` meet its target. The nonce space is
+// interleaved across `threads` workers: worker i tries i, i + threads, i + 2 * threads...
+// Returns None when `cancel` is set before a solution is found.
+pub fn mine(
+    header: &Blockheader,
+    threads: usize,
+    cancel: &AtomicBool,
+) -> (Option<Blockheader>, MiningStats) {
+    let threads = threads.max(1) as u64;
+    let start = Instant::now();
+    let hashes = AtomicU64::new(0);
+    let found = AtomicBool::new(false);
+    let solution = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for worker in 0..threads {
+            let mut header = header.clone();
+            let (hashes, found, solution) = (&hashes, &found, &solution);
+            scope.spawn(move || {
+                let mut count = 0;
+                header.nonce = worker;
+                while !found.load(Ordering::Relaxed) && !cancel.load(Ordering::Relaxed) {
+                    count += 1;
+                    if pow::meets_target(&header.hash_bytes(), header.bits) {
+                        if !found.swap(true, Ordering::Relaxed) {
+                            *solution.lock().unwrap() = Some(header.clone());
+                        }
+                        break;
+                    }
+                    header.nonce = header.nonce.wrapping_add(threads);
+                }
+                hashes.fetch_add(count, Ordering::Relaxed);
+            });
+        }
+    });
+
+    let stats = MiningStats {
+        hashes: hashes.into_inner(),
+        elapsed: start.elapsed(),
+    };
+    (solution.into_inner().unwrap(), stats)
+}
+
+pub fn default_threads() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
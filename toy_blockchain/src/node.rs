@@ -0,0 +1,468 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::blockchain::{Block, BlockStatus, Blockheader, Chain, Transaction, TransactionProof};
+use crate::env::Logger;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+// Longest message read from a connection, a whole chain has to fit
+const MAX_MESSAGE: u64 = 64 << 20;
+// Most peers a node keeps, each message is sent to all of them
+const MAX_PEERS: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Message {
+    Transaction(Transaction),
+    Block(Block),
+    GetChain,
+    Chain(Vec<Block>),
+    // Answers GetChain on a pruned node: the hash of the block at `height`
+    // the chain was pruned at, and the blocks after it
+    Pruned {
+        height: usize,
+        hash: String,
+        blocks: Vec<Block>,
+    },
+    // Headers of the best chain from height `from` on, for light clients
+    GetHeaders {
+        from: usize,
+    },
+    Headers(Vec<Blockheader>),
+    GetProof {
+        id: String,
+    },
+    Proof(Option<TransactionProof>),
+    // Checks that a node listens at the address it claims, answered with Ok
+    Ping,
+    Ok,
+}
+
+// Every message carries the listening address of the node that sent it,
+// so the receiver can answer and learn about new peers. Light clients
+// don't listen and leave it empty.
+#[derive(Serialize, Deserialize, Debug)]
+struct Envelope {
+    from: String,
+    message: Message,
+}
+
+// A node shares its chain with the peers over TCP. Each message is sent as
+// one JSON line on a new connection, followed by one JSON line as reply.
+#[derive(Clone)]
+pub struct Node {
+    address: String,
+    peers: Arc<Mutex<Vec<String>>>,
+    chain: Arc<Mutex<Chain>>,
+    // The chain's logger, so an embedded node is as quiet as its chain
+    logger: Arc<dyn Logger>,
+}
+
+impl Node {
+    pub fn start(
+        address: String,
+        peers: Vec<String>,
+        chain: Arc<Mutex<Chain>>,
+    ) -> io::Result<Node> {
+        let listener = TcpListener::bind(&address)?;
+        let logger = chain.lock().unwrap().logger();
+        logger.info(&format!("Node listening on {}", address));
+
+        let node = Node {
+            address,
+            peers: Arc::new(Mutex::new(peers)),
+            chain,
+            logger,
+        };
+
+        let server = node.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let node = server.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = node.handle_connection(stream) {
+                                node.logger.error(&format!("Connection error: {}", e));
+                            }
+                        });
+                    }
+                    Err(e) => server.logger.error(&format!("Connection failed: {}", e)),
+                }
+            }
+        });
+
+        node.sync();
+        Ok(node)
+    }
+
+    // Asks every peer for its chain and switches to it if it has more work.
+    pub fn sync(&self) {
+        for peer in self.peers() {
+            self.sync_from(&peer);
+        }
+    }
+
+    pub fn broadcast_transaction(&self, transaction: Transaction) {
+        self.broadcast(Message::Transaction(transaction), None);
+    }
+
+    pub fn broadcast_block(&self, block: Block) {
+        self.broadcast(Message::Block(block), None);
+    }
+
+    fn peers(&self) -> Vec<String> {
+        self.peers.lock().unwrap().clone()
+    }
+
+    // Adds the node that sent a message once it answers at the address it
+    // claims, so a connection can't fill the peer list with made-up addresses.
+    fn learn_peer(&self, peer: &str) {
+        let known = |peers: &[String]| {
+            peer == self.address || peers.len() >= MAX_PEERS || peers.iter().any(|p| p == peer)
+        };
+        if known(&self.peers()) {
+            return;
+        }
+        if !matches!(send(peer, &self.envelope(Message::Ping)), Ok(Message::Ok)) {
+            return;
+        }
+        let mut peers = self.peers.lock().unwrap();
+        if !known(&peers) {
+            self.logger.info(&format!("New peer: {}", peer));
+            peers.push(peer.to_string());
+        }
+    }
+
+    fn broadcast(&self, message: Message, skip: Option<&str>) {
+        let line = self.envelope(message);
+        for peer in self.peers() {
+            if Some(peer.as_str()) == skip {
+                continue;
+            }
+            let line = line.clone();
+            let logger = self.logger.clone();
+            thread::spawn(move || {
+                if let Err(e) = send(&peer, &line) {
+                    logger.error(&format!("Unable to reach peer {}: {}", peer, e));
+                }
+            });
+        }
+    }
+
+    fn sync_from(&self, peer: &str) {
+        let reply = match send(peer, &self.envelope(Message::GetChain)) {
+            Ok(reply) => reply,
+            Err(e) => {
+                self.logger
+                    .error(&format!("Unable to sync with {}: {}", peer, e));
+                return;
+            }
+        };
+        let mut chain = self.chain.lock().unwrap();
+        let blocks = match reply {
+            Message::Chain(blocks) => blocks,
+            // The pruned blocks can't be validated, only a chain that has
+            // them already can take the rest
+            Message::Pruned {
+                height,
+                hash,
+                blocks,
+            } => {
+                if chain.chain.get(height).map(|block| block.hash()) != Some(hash) {
+                    self.logger.error(&format!(
+                        "{} is pruned up to block {}, bootstrap from a snapshot of it to sync",
+                        peer, height
+                    ));
+                    return;
+                }
+                blocks
+            }
+            _ => return,
+        };
+        match chain.add_blocks(blocks) {
+            Ok(true) => self.logger.info(&format!(
+                "Switched to the chain with more work from {}",
+                peer
+            )),
+            Ok(false) => {}
+            Err(e) => self
+                .logger
+                .error(&format!("Rejected chain from {}: {}", peer, e)),
+        }
+    }
+
+    fn envelope(&self, message: Message) -> String {
+        let envelope = Envelope {
+            from: self.address.clone(),
+            message,
+        };
+        serde_json::to_string(&envelope).unwrap()
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let envelope: Envelope = read_message(&stream)?;
+        // Answering a ping must not ping back, or two new nodes would never stop
+        if !envelope.from.is_empty() && !matches!(envelope.message, Message::Ping) {
+            self.learn_peer(&envelope.from);
+        }
+
+        let reply = match envelope.message {
+            Message::Transaction(transaction) => {
+                let res = self
+                    .chain
+                    .lock()
+                    .unwrap()
+                    .new_transaction(transaction.clone());
+                if res.is_ok() {
+                    self.logger
+                        .info(&format!("Received transaction {}", transaction.id()));
+                    self.broadcast(Message::Transaction(transaction), Some(&envelope.from));
+                }
+                Message::Ok
+            }
+            Message::Block(block) => {
+                let hash = block.hash();
+                let res = self.chain.lock().unwrap().add_block(block.clone());
+                match res {
+                    Ok(BlockStatus::Known) => {}
+                    // The parent is missing, fetch the sender's chain
+                    Ok(BlockStatus::Orphan) => {
+                        self.logger.info(&format!("Received orphan block {}", hash));
+                        let node = self.clone();
+                        let peer = envelope.from.clone();
+                        thread::spawn(move || node.sync_from(&peer));
+                    }
+                    // Only blocks that joined the best chain are passed on,
+                    // peers fetch the rest of a branch once it wins
+                    // Too little work to wait for a parent, a peer with a
+                    // better chain announces its tip again
+                    Ok(BlockStatus::Dropped) => {
+                        self.logger.info(&format!("Dropped orphan block {}", hash))
+                    }
+                    Ok(BlockStatus::Side) => self
+                        .logger
+                        .info(&format!("Received block {} on a side branch", hash)),
+                    Ok(status) => {
+                        match status {
+                            BlockStatus::Reorganized { reverted } => self.logger.info(&format!(
+                                "Received block {}, switched branches rolling back {} blocks",
+                                hash, reverted
+                            )),
+                            _ => self.logger.info(&format!("Received block {}", hash)),
+                        }
+                        self.broadcast(Message::Block(block), Some(&envelope.from));
+                    }
+                    Err(e) => self
+                        .logger
+                        .error(&format!("Rejected block {}: {}", hash, e)),
+                }
+                Message::Ok
+            }
+            Message::GetChain => {
+                let chain = self.chain.lock().unwrap();
+                match chain.pruned_height() {
+                    Some(height) => Message::Pruned {
+                        height,
+                        hash: chain.chain[height].hash(),
+                        blocks: chain.chain[height + 1..].to_vec(),
+                    },
+                    None => Message::Chain(chain.chain.clone()),
+                }
+            }
+            Message::GetHeaders { from } => {
+                let chain = self.chain.lock().unwrap();
+                let blocks = chain.chain.get(from..).unwrap_or_default();
+                Message::Headers(blocks.iter().map(|block| block.header.clone()).collect())
+            }
+            Message::GetProof { id } => {
+                Message::Proof(self.chain.lock().unwrap().transaction_proof(&id))
+            }
+            Message::Chain(_)
+            | Message::Pruned { .. }
+            | Message::Headers(_)
+            | Message::Proof(_)
+            | Message::Ping
+            | Message::Ok => Message::Ok,
+        };
+
+        let mut reply = serde_json::to_string(&reply).unwrap();
+        reply.push('\n');
+        stream.write_all(reply.as_bytes())
+    }
+}
+
+// Sends `message` to `peer` without being a node, and returns the reply.
+pub fn request(peer: &str, message: Message) -> io::Result<Message> {
+    let envelope = Envelope {
+        from: String::new(),
+        message,
+    };
+    send(peer, &serde_json::to_string(&envelope).unwrap())
+}
+
+fn send(peer: &str, line: &str) -> io::Result<Message> {
+    let addr = peer
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid peer address"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.write_all(line.as_bytes())?;
+    stream.write_all(b"\n")?;
+    read_message(&stream)
+}
+
+// Reads one JSON line of at most MAX_MESSAGE bytes.
+fn read_message<T: serde::de::DeserializeOwned>(stream: &TcpStream) -> io::Result<T> {
+    let mut line = String::new();
+    BufReader::new(stream.take(MAX_MESSAGE)).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the message is cut off or too long",
+        ));
+    }
+    serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// One peer address per line, empty lines and lines starting with '#' are ignored.
+pub fn load_peers(path: &str) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::blockchain::Params;
+    use crate::env::{Env, FixedClock, QuietLogger};
+    use crate::light::{LightClient, LightError};
+    use crate::pow;
+    use std::time::Instant;
+
+    // Chains created with the same bits share their genesis block.
+    fn new_chain(zero_bits: u32) -> Arc<Mutex<Chain>> {
+        let env = Env {
+            clock: Arc::new(FixedClock::new(1_700_000_000_000, 1_000)),
+            logger: Arc::new(QuietLogger),
+        };
+        let bits = pow::bits_from_zero_bits(zero_bits).unwrap();
+        let mut chain = Chain::new(
+            "miner".to_string(),
+            Amount::from_coins(100).unwrap(),
+            bits,
+            Params::default(),
+            env,
+        );
+        chain.miner_threads = 1;
+        Arc::new(Mutex::new(chain))
+    }
+
+    fn free_address() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[test]
+    fn peers_are_learned_once_they_answer() {
+        let node = Node::start(free_address(), Vec::new(), new_chain(4)).unwrap();
+        let envelope = |from: &str| {
+            let envelope = Envelope {
+                from: from.to_string(),
+                message: Message::GetHeaders { from: 0 },
+            };
+            serde_json::to_string(&envelope).unwrap()
+        };
+
+        send(&node.address, &envelope(&free_address())).unwrap();
+        assert!(node.peers().is_empty());
+
+        let other = Node::start(free_address(), Vec::new(), new_chain(4)).unwrap();
+        send(&node.address, &envelope(&other.address)).unwrap();
+        assert_eq!(node.peers(), [other.address]);
+    }
+
+    #[test]
+    fn nodes_agree_on_mined_blocks() {
+        let first = Node::start(free_address(), Vec::new(), new_chain(4)).unwrap();
+        let second =
+            Node::start(free_address(), vec![first.address.clone()], new_chain(4)).unwrap();
+        assert_eq!(
+            first.chain.lock().unwrap().last_hash(),
+            second.chain.lock().unwrap().last_hash()
+        );
+
+        for _ in 0..3 {
+            let block = {
+                let mut chain = first.chain.lock().unwrap();
+                assert!(chain.generate_new_block());
+                chain.chain.last().unwrap().clone()
+            };
+            first.broadcast_block(block);
+        }
+
+        let expected = first.chain.lock().unwrap().last_hash();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while second.chain.lock().unwrap().last_hash() != expected {
+            assert!(Instant::now() < deadline, "the second node never caught up");
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(second.chain.lock().unwrap().chain.len(), 4);
+    }
+
+    #[test]
+    fn light_clients_only_follow_their_genesis_block() {
+        let honest = Node::start(free_address(), Vec::new(), new_chain(4)).unwrap();
+        // Easier bits than the real chain, so its blocks are cheap to make up
+        let made_up = Node::start(free_address(), Vec::new(), new_chain(1)).unwrap();
+        for node in [&honest, &made_up] {
+            let mut chain = node.chain.lock().unwrap();
+            for _ in 0..3 {
+                assert!(chain.generate_new_block());
+            }
+        }
+        let genesis = honest.chain.lock().unwrap().chain[0].hash();
+
+        let mut client = LightClient::new(&Params::default(), genesis);
+        assert!(matches!(
+            client.sync(&made_up.address),
+            Err(LightError::Genesis { .. })
+        ));
+        assert!(client.headers.is_empty());
+        assert!(matches!(client.sync(&honest.address), Ok(true)));
+        assert_eq!(client.headers.len(), 4);
+    }
+
+    #[test]
+    fn pruned_nodes_only_serve_peers_with_their_blocks() {
+        let pruned = Node::start(free_address(), Vec::new(), new_chain(4)).unwrap();
+        let synced = Node::start(free_address(), Vec::new(), new_chain(4)).unwrap();
+        let fresh = Node::start(free_address(), Vec::new(), new_chain(4)).unwrap();
+        {
+            let mut chain = pruned.chain.lock().unwrap();
+            for _ in 0..3 {
+                assert!(chain.generate_new_block());
+            }
+            let blocks = chain.chain[1..3].to_vec();
+            synced.chain.lock().unwrap().add_blocks(blocks).unwrap();
+            let snapshot = chain.snapshot(2).unwrap();
+            chain.prune(snapshot).unwrap();
+        }
+
+        synced.sync_from(&pruned.address);
+        fresh.sync_from(&pruned.address);
+        let tip = pruned.chain.lock().unwrap().last_hash();
+        assert_eq!(synced.chain.lock().unwrap().last_hash(), tip);
+        assert_eq!(fresh.chain.lock().unwrap().chain.len(), 1);
+    }
+}
@@ -0,0 +1,158 @@
+use num_bigint::BigUint;
+use num_traits::{One, ToPrimitive, Zero};
+
+// Proof-of-work targets are 256-bit numbers stored in the compact "bits"
+// format: the high byte is the length of the target in bytes and the low
+// 23 bits are its most significant digits. A hash meets the target when,
+// read as a big-endian number, it is less than or equal to the target.
+
+pub fn target_from_bits(bits: u32) -> Option<BigUint> {
+    let size = bits >> 24;
+    let mantissa = bits & 0x007f_ffff;
+    // The sign bit is never set by bits_from_target
+    if bits & 0x0080_0000 != 0 || mantissa == 0 {
+        return None;
+    }
+
+    let target = if size <= 3 {
+        BigUint::from(mantissa >> (8 * (3 - size)))
+    } else {
+        BigUint::from(mantissa) << (8 * (size - 3)) as usize
+    };
+    if target.is_zero() || target.bits() > 256 {
+        return None;
+    }
+    Some(target)
+}
+
+pub fn bits_from_target(target: &BigUint) -> u32 {
+    let mut size = target.to_bytes_be().len() as u32;
+    let mut mantissa = if size <= 3 {
+        target.to_u32().unwrap() << (8 * (3 - size))
+    } else {
+        (target >> (8 * (size - 3)) as usize).to_u32().unwrap()
+    };
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    size << 24 | mantissa
+}
+
+// Target that requires `zero_bits` leading zero bits in the hash.
+pub fn bits_from_zero_bits(zero_bits: u32) -> Option<u32> {
+    if zero_bits >= 256 {
+        return None;
+    }
+    let target = (BigUint::one() << (256 - zero_bits) as usize) - BigUint::one();
+    Some(bits_from_target(&target))
+}
+
+// The expected number of leading zero bits, a readable measure of difficulty.
+pub fn zero_bits(bits: u32) -> f64 {
+    match target_from_bits(bits).and_then(|target| target.to_f64()) {
+        Some(target) => 256.0 - (target + 1.0).log2(),
+        None => f64::NAN,
+    }
+}
+
+// Scales the target by actual / expected time, limited to a factor of 4 either way.
+pub fn retarget(bits: u32, actual: i64, expected: i64) -> u32 {
+    let target = match target_from_bits(bits) {
+        Some(target) => target,
+        None => return bits,
+    };
+    let expected = expected.max(1);
+    let actual = actual.clamp(expected / 4, expected * 4).max(1);
+
+    let max_target = (BigUint::one() << 256usize) - BigUint::one();
+    let target = (target * actual as u64 / expected as u64).clamp(BigUint::one(), max_target);
+    bits_from_target(&target)
+}
+
+pub fn meets_target(hash: &[u8], bits: u32) -> bool {
+    match target_from_bits(bits) {
+        Some(target) => BigUint::from_bytes_be(hash) <= target,
+        None => false,
+    }
+}
+
+// Expected number of hashes to find a block at `bits`: 2^256 / (target + 1).
+pub fn work(bits: u32) -> BigUint {
+    match target_from_bits(bits) {
+        Some(target) => (BigUint::one() << 256usize) / (target + BigUint::one()),
+        None => BigUint::zero(),
+    }
+}
+
+// Accepts either a number of leading zero bits ("20") or compact bits in hex ("0x1e0fffff").
+pub fn parse_difficulty(input: &str) -> Option<u32> {
+    let input = input.trim();
+    match input.strip_prefix("0x") {
+        Some(hex) => {
+            let bits = u32::from_str_radix(hex, 16).ok()?;
+            target_from_bits(bits).map(|_| bits)
+        }
+        None => bits_from_zero_bits(input.parse().ok()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Valid compact bits, from targets of any length
+    fn valid_bits() -> impl Strategy<Value = u32> {
+        prop::collection::vec(any::<u8>(), 1..=32).prop_filter_map("zero target", |bytes| {
+            let target = BigUint::from_bytes_be(&bytes);
+            match target.is_zero() {
+                true => None,
+                false => Some(bits_from_target(&target)),
+            }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn compact_targets_round_down(bytes in prop::collection::vec(any::<u8>(), 1..=32)) {
+            let target = BigUint::from_bytes_be(&bytes);
+            prop_assume!(!target.is_zero());
+            let bits = bits_from_target(&target);
+            let compact = target_from_bits(bits).unwrap();
+            prop_assert!(compact <= target);
+            prop_assert_eq!(bits_from_target(&compact), bits);
+        }
+
+        #[test]
+        fn hashes_meet_targets_they_dont_exceed(bits in valid_bits(), hash in any::<[u8; 32]>()) {
+            let target = target_from_bits(bits).unwrap();
+            let meets = BigUint::from_bytes_be(&hash) <= target;
+            prop_assert_eq!(meets_target(&hash, bits), meets);
+        }
+
+        #[test]
+        fn easier_targets_take_less_work(a in valid_bits(), b in valid_bits()) {
+            let (easy, hard) = match target_from_bits(a) >= target_from_bits(b) {
+                true => (a, b),
+                false => (b, a),
+            };
+            prop_assert!(work(easy) <= work(hard));
+            prop_assert!(work(easy) >= BigUint::one());
+        }
+
+        #[test]
+        fn retargets_stay_within_a_factor_of_four(
+            bits in valid_bits(),
+            actual in -1_000_000i64..1_000_000_000,
+            expected in 1_000i64..1_000_000,
+        ) {
+            let target = target_from_bits(bits).unwrap();
+            let next = target_from_bits(retarget(bits, actual, expected)).unwrap();
+            let max_target = (BigUint::one() << 256usize) - BigUint::one();
+            prop_assert!(next <= (&target * 4u32).min(max_target));
+            // Integer division and compact bits only round down a little
+            prop_assert!((next + 1u32) * 5u32 >= target);
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+
+use crate::encoding::Encoder;
+use crate::keys;
+
+const LOCK_PREFIX: &str = "lock:";
+
+// Conditions that coins sent to a lock address can be spent under. The lock
+// address is the hash of the conditions, so whoever funds it only needs the
+// address, and the conditions are revealed once the coins are spent.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Condition {
+    // Spendable from the block at `height` on
+    After {
+        height: u64,
+    },
+    // Signed by `required` of the `signers`
+    Multisig {
+        required: usize,
+        signers: Vec<String>,
+    },
+    // Reveals a preimage whose SHA-256 hash in hex is `hash`
+    HashLock {
+        hash: String,
+    },
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+}
+
+// What a transaction spending from a lock address carries instead of the
+// sender's signature.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Witness {
+    pub conditions: Condition,
+    // Signatures over the signing bytes of the transaction by signer address
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub signatures: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preimage: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum WitnessError {
+    Missing,
+    Unexpected,
+    Legacy,
+    Mismatch,
+    Unused,
+    Required { required: usize, signers: usize },
+    Unsatisfied { height: u64 },
+}
+
+impl fmt::Display for WitnessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WitnessError::Missing => write!(f, "spending from a lock address needs a witness"),
+            WitnessError::Unexpected => write!(f, "only lock addresses are spent with a witness"),
+            WitnessError::Legacy => write!(f, "witnesses need encoding version 1 or later"),
+            WitnessError::Mismatch => {
+                write!(f, "the witness conditions don't hash to the sender address")
+            }
+            WitnessError::Unused => write!(
+                f,
+                "the witness carries a signature or preimage the conditions don't use"
+            ),
+            WitnessError::Required { required, signers } => write!(
+                f,
+                "a multisig can't require {} of {} distinct signers",
+                required, signers
+            ),
+            WitnessError::Unsatisfied { height } => {
+                write!(
+                    f,
+                    "the witness doesn't meet the conditions at block {}",
+                    height
+                )
+            }
+        }
+    }
+}
+
+impl Condition {
+    // The address coins are locked under.
+    pub fn address(&self) -> String {
+        let mut encoder = Encoder::new();
+        self.encode(&mut encoder);
+        let digest = Sha256::digest(encoder.finish());
+        format!("{}{}", LOCK_PREFIX, hex::encode(digest))
+    }
+
+    pub fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            Condition::After { height } => {
+                encoder.u32(0).u64(*height);
+            }
+            Condition::Multisig { required, signers } => {
+                encoder
+                    .u32(1)
+                    .u64(*required as u64)
+                    .u32(signers.len() as u32);
+                for signer in signers {
+                    encoder.str(signer);
+                }
+            }
+            Condition::HashLock { hash } => {
+                encoder.u32(2).str(hash);
+            }
+            Condition::All(conditions) | Condition::Any(conditions) => {
+                let tag = match self {
+                    Condition::All(_) => 3,
+                    _ => 4,
+                };
+                encoder.u32(tag).u32(conditions.len() as u32);
+                for condition in conditions {
+                    condition.encode(encoder);
+                }
+            }
+        }
+    }
+
+    // Checks that every multisig requires between one and all of its
+    // distinct signers, so nothing is spendable by anyone or by no one.
+    pub fn check(&self) -> Result<(), WitnessError> {
+        match self {
+            Condition::Multisig { required, signers } => {
+                let signers = signers.iter().collect::<BTreeSet<_>>().len();
+                match (1..=signers).contains(required) {
+                    true => Ok(()),
+                    false => Err(WitnessError::Required {
+                        required: *required,
+                        signers,
+                    }),
+                }
+            }
+            Condition::All(conditions) | Condition::Any(conditions) => {
+                conditions.iter().try_for_each(Condition::check)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Whether this condition or one nested in it matches.
+    fn contains(&self, matches: &dyn Fn(&Condition) -> bool) -> bool {
+        match self {
+            Condition::All(conditions) | Condition::Any(conditions) => {
+                matches(self)
+                    || conditions
+                        .iter()
+                        .any(|condition| condition.contains(matches))
+            }
+            _ => matches(self),
+        }
+    }
+
+    // Whether `witness` meets the conditions for a transaction with the
+    // signing bytes `message` in the block at `height`.
+    fn satisfied(&self, witness: &Witness, message: &[u8], height: u64) -> bool {
+        match self {
+            Condition::After { height: after } => height >= *after,
+            Condition::Multisig { required, signers } => {
+                let signers: BTreeSet<&String> = signers.iter().collect();
+                let signed = signers
+                    .into_iter()
+                    .filter(|signer| match witness.signatures.get(*signer) {
+                        Some(signature) => keys::verify(signer, message, signature),
+                        None => false,
+                    })
+                    .count();
+                signed >= *required
+            }
+            Condition::HashLock { hash } => match &witness.preimage {
+                Some(preimage) => hex::encode(Sha256::digest(preimage.as_bytes())) == *hash,
+                None => false,
+            },
+            Condition::All(conditions) => conditions
+                .iter()
+                .all(|condition| condition.satisfied(witness, message, height)),
+            Condition::Any(conditions) => conditions
+                .iter()
+                .any(|condition| condition.satisfied(witness, message, height)),
+        }
+    }
+}
+
+impl Witness {
+    // Checks the witness of a transaction sent from the lock address `sender`.
+    pub fn check(&self, sender: &str, message: &[u8], height: u64) -> Result<(), WitnessError> {
+        if self.conditions.address() != sender {
+            return Err(WitnessError::Mismatch);
+        }
+        self.conditions.check()?;
+        // Anything else would only pad the transaction
+        let signer = |address: &String| {
+            self.conditions.contains(&|condition| match condition {
+                Condition::Multisig { signers, .. } => signers.contains(address),
+                _ => false,
+            })
+        };
+        let hash_lock = || {
+            self.conditions
+                .contains(&|condition| matches!(condition, Condition::HashLock { .. }))
+        };
+        let signed = self.signatures.iter().all(|(address, signature)| {
+            signer(address) && keys::verify(address, message, signature)
+        });
+        if !signed || (self.preimage.is_some() && !hash_lock()) {
+            return Err(WitnessError::Unused);
+        }
+        match self.conditions.satisfied(self, message, height) {
+            true => Ok(()),
+            false => Err(WitnessError::Unsatisfied { height }),
+        }
+    }
+
+    pub fn encode(&self, encoder: &mut Encoder) {
+        self.conditions.encode(encoder);
+        encoder.u32(self.signatures.len() as u32);
+        for (signer, signature) in &self.signatures {
+            encoder.str(signer).str(signature);
+        }
+        encoder.str(self.preimage.as_deref().unwrap_or_default());
+    }
+}
+
+pub fn is_lock_address(address: &str) -> bool {
+    address.starts_with(LOCK_PREFIX)
+}
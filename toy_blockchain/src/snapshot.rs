@@ -0,0 +1,67 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::amount::Amount;
+use crate::blockchain::{Block, Chain};
+use crate::encoding::Encoder;
+use crate::ledger::Ledger;
+
+// The state of the chain after the block at `height`: every non-zero balance
+// and the ids of the mined transactions, which keep them from being mined
+// again. The blocks up to `height` are kept without their transactions, so
+// their headers can still be checked and the chain can continue on top.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub height: usize,
+    pub hash: String,
+    // Hash of the height, block hash, balances and transaction ids
+    pub commitment: String,
+    pub balances: BTreeMap<String, Amount>,
+    pub transactions: BTreeSet<String>,
+    pub blocks: Vec<Block>,
+}
+
+impl Snapshot {
+    // Takes a snapshot after the last of `blocks`, whose transactions led to `ledger`.
+    pub fn new(blocks: &[Block], ledger: &Ledger) -> Snapshot {
+        let last = blocks.last().expect("a snapshot needs the genesis block");
+        let mut snapshot = Snapshot {
+            height: blocks.len() - 1,
+            hash: last.hash(),
+            commitment: String::new(),
+            balances: ledger
+                .balances()
+                .filter(|(_, amount)| !amount.is_zero())
+                .map(|(address, amount)| (address.clone(), *amount))
+                .collect(),
+            transactions: ledger.transactions().cloned().collect(),
+            blocks: blocks.iter().map(Block::pruned).collect(),
+        };
+        snapshot.commitment = snapshot.compute_commitment();
+        snapshot
+    }
+
+    // The commitment is computed from sorted state, so every node that
+    // reached the same block gets the same one.
+    pub fn compute_commitment(&self) -> String {
+        let mut encoder = Encoder::new();
+        encoder
+            .u64(self.height as u64)
+            .str(&self.hash)
+            .u32(self.balances.len() as u32);
+        for (address, amount) in &self.balances {
+            encoder.str(address).u64(amount.units());
+        }
+        encoder.u32(self.transactions.len() as u32);
+        for id in &self.transactions {
+            encoder.str(id);
+        }
+        Chain::hex_to_string(&Chain::digest(&encoder.finish()))
+    }
+
+    pub fn ledger(&self) -> Ledger {
+        Ledger::from_state(
+            self.balances.clone().into_iter().collect(),
+            self.transactions.iter().cloned().collect(),
+        )
+    }
+}
@@ -0,0 +1,208 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::amount::{Amount, StoredAmount};
+use crate::blockchain::{Block, Params, Transaction};
+use crate::snapshot::Snapshot;
+
+const BLOCKS_FILE: &str = "blocks.jsonl";
+const PENDING_FILE: &str = "pending.json";
+const CONFIG_FILE: &str = "config.json";
+const BASE_SNAPSHOT_FILE: &str = "base_snapshot.json";
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+// Settings chosen when the chain was created.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "StoredConfig")]
+pub struct Config {
+    pub miner_address: String,
+    pub reward: Amount,
+    pub bits: u32,
+    pub params: Params,
+    // Absolute path of the key this node seals blocks with under proof of authority
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer_key_file: Option<String>,
+    // Blocks between stored snapshots, 0 to take none
+    #[serde(default)]
+    pub snapshot_interval: u64,
+}
+
+// Settings as read back. Chains created before base units stored the reward
+// as floating point coins, and their encoding version is still 0 or 1.
+#[derive(Deserialize)]
+struct StoredConfig {
+    miner_address: String,
+    reward: StoredAmount,
+    bits: u32,
+    params: Params,
+    #[serde(default)]
+    signer_key_file: Option<String>,
+    #[serde(default)]
+    snapshot_interval: u64,
+}
+
+impl TryFrom<StoredConfig> for Config {
+    type Error = String;
+
+    fn try_from(stored: StoredConfig) -> Result<Config, String> {
+        if stored.params.retarget_interval == 1 {
+            return Err("retarget interval must be 0 or at least 2 blocks".to_string());
+        }
+        Ok(Config {
+            miner_address: stored.miner_address,
+            reward: stored.reward.into_amount(stored.params.version)?,
+            bits: stored.bits,
+            params: stored.params,
+            signer_key_file: stored.signer_key_file,
+            snapshot_interval: stored.snapshot_interval,
+        })
+    }
+}
+
+// On-disk layout: one JSON encoded block per line in an append-only file,
+// plus small files holding the pending transactions and the chain settings.
+// Snapshots are stored by height in a directory, and a pruned chain keeps the
+// snapshot it was pruned at next to its blocks.
+pub struct Storage {
+    dir: PathBuf,
+}
+
+impl Storage {
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Storage> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Storage {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    pub fn load_blocks(&self) -> io::Result<Vec<Block>> {
+        let file = match File::open(self.dir.join(BLOCKS_FILE)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut blocks = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // Headers were only hashed from their JSON then, so `difficulty`
+            // can't be read as `bits` without changing every hash
+            let block = serde_json::from_str(&line).map_err(|e| {
+                match line.contains("\"difficulty\":") {
+                true => io::Error::new(
+                    ErrorKind::InvalidData,
+                    "the blocks are from before compact targets and store a difficulty instead \
+                     of bits, move them away and create the chain again",
+                ),
+                false => invalid_data(e),
+            }
+            })?;
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    pub fn append_block(&self, block: &Block) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(BLOCKS_FILE))?;
+        let line = serde_json::to_string(block).map_err(invalid_data)?;
+        writeln!(file, "{}", line)?;
+        file.sync_data()
+    }
+
+    // Rewrites the whole block file, used when the chain is replaced by a longer one.
+    pub fn replace_blocks(&self, blocks: &[Block]) -> io::Result<()> {
+        let mut data = String::new();
+        for block in blocks {
+            data.push_str(&serde_json::to_string(block).map_err(invalid_data)?);
+            data.push('\n');
+        }
+        write_atomic(&self.dir.join(BLOCKS_FILE), data.as_bytes())
+    }
+
+    pub fn load_pending(&self) -> io::Result<Vec<Transaction>> {
+        match fs::read_to_string(self.dir.join(PENDING_FILE)) {
+            Ok(data) => serde_json::from_str(&data).map_err(invalid_data),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save_pending(&self, transactions: &[Transaction]) -> io::Result<()> {
+        let data = serde_json::to_string(transactions).map_err(invalid_data)?;
+        write_atomic(&self.dir.join(PENDING_FILE), data.as_bytes())
+    }
+
+    pub fn load_config(&self) -> io::Result<Option<Config>> {
+        match fs::read_to_string(self.dir.join(CONFIG_FILE)) {
+            Ok(data) => serde_json::from_str(&data).map(Some).map_err(invalid_data),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save_config(&self, config: &Config) -> io::Result<()> {
+        let data = serde_json::to_string_pretty(config).map_err(invalid_data)?;
+        write_atomic(&self.dir.join(CONFIG_FILE), data.as_bytes())
+    }
+
+    pub fn load_base_snapshot(&self) -> io::Result<Option<Snapshot>> {
+        match fs::read_to_string(self.dir.join(BASE_SNAPSHOT_FILE)) {
+            Ok(data) => serde_json::from_str(&data).map(Some).map_err(invalid_data),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save_base_snapshot(&self, snapshot: &Snapshot) -> io::Result<()> {
+        let data = serde_json::to_string(snapshot).map_err(invalid_data)?;
+        write_atomic(&self.dir.join(BASE_SNAPSHOT_FILE), data.as_bytes())
+    }
+
+    // Stores `snapshot` as `snapshots/<height>.json` and returns its path.
+    pub fn save_snapshot(&self, snapshot: &Snapshot) -> io::Result<PathBuf> {
+        let dir = self.dir.join(SNAPSHOTS_DIR);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.json", snapshot.height));
+        let data = serde_json::to_string(snapshot).map_err(invalid_data)?;
+        write_atomic(&path, data.as_bytes())?;
+        Ok(path)
+    }
+
+    pub fn latest_snapshot_height(&self) -> io::Result<Option<usize>> {
+        let entries = match fs::read_dir(self.dir.join(SNAPSHOTS_DIR)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut latest = None;
+        for entry in entries {
+            let name = entry?.file_name();
+            let height = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|height| height.parse().ok());
+            latest = latest.max(height);
+        }
+        Ok(latest)
+    }
+}
+
+// Write to a temporary file first so a crash never leaves a half written file behind.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_data()?;
+    fs::rename(tmp, path)
+}
+
+fn invalid_data(e: serde_json::Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, e)
+}
@@ -0,0 +1,537 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::SigningKey;
+use rand::{CryptoRng, Rng, RngCore};
+use sha2::{Digest, Sha256};
+
+use crate::amount::Amount;
+use crate::blockchain::{Chain, Transaction};
+use crate::keys;
+use crate::ledger::ROOT_ADDRESS;
+use crate::storage;
+
+const KEYSTORE_VERSION: u32 = 1;
+// Rounds of PBKDF2-HMAC-SHA256 that turn the passphrase into the encryption key.
+// Wallets store their rounds, so tests can make cheap ones.
+#[cfg(not(test))]
+const ITERATIONS: u32 = 100_000;
+#[cfg(test)]
+const ITERATIONS: u32 = 1_000;
+
+#[derive(Debug)]
+pub enum WalletError {
+    Io(io::Error),
+    Invalid(String),
+    Exists,
+    Passphrase,
+    UnknownAccount(String),
+    DuplicateAccount(String),
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalletError::Io(e) => write!(f, "{}", e),
+            WalletError::Invalid(reason) => write!(f, "invalid wallet file: {}", reason),
+            WalletError::Exists => write!(f, "the wallet already exists"),
+            WalletError::Passphrase => write!(f, "wrong passphrase"),
+            WalletError::UnknownAccount(name) => write!(f, "no account named {}", name),
+            WalletError::DuplicateAccount(name) => {
+                write!(f, "the wallet already holds the account or key {}", name)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for WalletError {
+    fn from(e: io::Error) -> WalletError {
+        WalletError::Io(e)
+    }
+}
+
+// A named key pair, only the secret key is encrypted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Account {
+    pub name: String,
+    pub address: String,
+    nonce: String,
+    secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Keystore {
+    version: u32,
+    salt: String,
+    iterations: u32,
+    // Hash of the derived key, to tell a wrong passphrase from a damaged file
+    check: String,
+    accounts: Vec<Account>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received,
+    Mined,
+}
+
+// A transaction from or to an account of the wallet.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: String,
+    // None while the transaction is pending
+    pub height: Option<usize>,
+    pub account: String,
+    pub direction: Direction,
+    // The account name when the other side is in the wallet too
+    pub counterparty: String,
+    pub amount: Amount,
+    pub fee: Amount,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.height {
+            Some(height) => write!(f, "{} ", height)?,
+            None => write!(f, "pending ")?,
+        }
+        write!(f, "{} {} ", self.account, self.amount)?;
+        match self.direction {
+            Direction::Sent => write!(f, "sent to {}", self.counterparty)?,
+            Direction::Received => write!(f, "received from {}", self.counterparty)?,
+            Direction::Mined => write!(f, "mined")?,
+        }
+        write!(f, " (fee {}) {}", self.fee, self.id)
+    }
+}
+
+// Named accounts whose secret keys are encrypted with ChaCha20-Poly1305
+// under a key derived from a passphrase. The wallet file is only written
+// with encrypted keys, the passphrase and derived key stay in memory.
+pub struct Wallet {
+    path: PathBuf,
+    keystore: Keystore,
+    key: [u8; 32],
+}
+
+impl Wallet {
+    pub fn create<R: RngCore + CryptoRng>(
+        path: &Path,
+        passphrase: &str,
+        rng: &mut R,
+    ) -> Result<Wallet, WalletError> {
+        if path.exists() {
+            return Err(WalletError::Exists);
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut salt = [0; 16];
+        rng.fill_bytes(&mut salt);
+        let key = derive_key(passphrase, &salt, ITERATIONS);
+        let wallet = Wallet {
+            path: path.to_path_buf(),
+            keystore: Keystore {
+                version: KEYSTORE_VERSION,
+                salt: hex::encode(salt),
+                iterations: ITERATIONS,
+                check: check(&key),
+                accounts: Vec::new(),
+            },
+            key,
+        };
+        wallet.save()?;
+        Ok(wallet)
+    }
+
+    pub fn open(path: &Path, passphrase: &str) -> Result<Wallet, WalletError> {
+        let data = fs::read_to_string(path)?;
+        let keystore: Keystore =
+            serde_json::from_str(&data).map_err(|e| WalletError::Invalid(e.to_string()))?;
+        if keystore.version != KEYSTORE_VERSION {
+            return Err(WalletError::Invalid(format!(
+                "unknown version {}",
+                keystore.version
+            )));
+        }
+        let salt = hex::decode(&keystore.salt)
+            .map_err(|_| WalletError::Invalid("the salt is not hex".to_string()))?;
+        let key = derive_key(passphrase, &salt, keystore.iterations);
+        if check(&key) != keystore.check {
+            return Err(WalletError::Passphrase);
+        }
+        Ok(Wallet {
+            path: path.to_path_buf(),
+            keystore,
+            key,
+        })
+    }
+
+    pub fn accounts(&self) -> &[Account] {
+        &self.keystore.accounts
+    }
+
+    pub fn account(&self, name: &str) -> Option<&Account> {
+        self.keystore
+            .accounts
+            .iter()
+            .find(|account| account.name == name)
+    }
+
+    // Encrypts `key` into the wallet under `name`.
+    pub fn add_account<R: RngCore + CryptoRng>(
+        &mut self,
+        name: &str,
+        key: &SigningKey,
+        rng: &mut R,
+    ) -> Result<&Account, WalletError> {
+        let address = keys::address(key);
+        let known = self
+            .keystore
+            .accounts
+            .iter()
+            .find(|account| account.name == name || account.address == address);
+        if let Some(account) = known {
+            let duplicate = match account.name == name {
+                true => name.to_string(),
+                false => address,
+            };
+            return Err(WalletError::DuplicateAccount(duplicate));
+        }
+
+        let mut nonce = [0; 12];
+        rng.fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: &key.to_bytes(),
+            aad: address.as_bytes(),
+        };
+        let secret = self
+            .cipher()
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .expect("a key fits into one message");
+        self.keystore.accounts.push(Account {
+            name: name.to_string(),
+            address,
+            nonce: hex::encode(nonce),
+            secret: hex::encode(secret),
+        });
+        if let Err(e) = self.save() {
+            self.keystore.accounts.pop();
+            return Err(e.into());
+        }
+        Ok(self.keystore.accounts.last().unwrap())
+    }
+
+    pub fn signing_key(&self, name: &str) -> Result<SigningKey, WalletError> {
+        let account = self
+            .account(name)
+            .ok_or_else(|| WalletError::UnknownAccount(name.to_string()))?;
+        let damaged = || WalletError::Invalid(format!("the key of {} is damaged", name));
+        let nonce = hex::decode(&account.nonce).map_err(|_| damaged())?;
+        let secret = hex::decode(&account.secret).map_err(|_| damaged())?;
+        if nonce.len() != 12 {
+            return Err(damaged());
+        }
+        let payload = Payload {
+            msg: &secret,
+            aad: account.address.as_bytes(),
+        };
+        let bytes: [u8; 32] = self
+            .cipher()
+            .decrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| damaged())?
+            .try_into()
+            .map_err(|_| damaged())?;
+        Ok(SigningKey::from_bytes(&bytes))
+    }
+
+    // The address of the account `name`, any other input is taken as an address.
+    pub fn resolve(&self, name: &str) -> String {
+        match self.account(name) {
+            Some(account) => account.address.clone(),
+            None => name.to_string(),
+        }
+    }
+
+    // Builds a transaction from the account `from` and signs it. `to` is an
+    // account name or an address.
+    pub fn transfer<R: Rng>(
+        &self,
+        rng: &mut R,
+        from: &str,
+        to: &str,
+        amount: Amount,
+        fee: Amount,
+        version: u32,
+    ) -> Result<Transaction, WalletError> {
+        let key = self.signing_key(from)?;
+        let receiver = self.resolve(to);
+        Ok(Transaction::signed(
+            rng, &key, receiver, amount, fee, version,
+        ))
+    }
+
+    // The transactions of the accounts, from the oldest mined one to the
+    // pending ones. Pruned blocks no longer show up.
+    pub fn history(&self, chain: &Chain) -> Vec<Entry> {
+        let mined = chain.chain.iter().enumerate().flat_map(|(height, block)| {
+            block
+                .transactions
+                .iter()
+                .map(move |transaction| (Some(height), transaction))
+        });
+        let pending = chain
+            .mempool
+            .transactions()
+            .iter()
+            .map(|transaction| (None, transaction));
+
+        let mut entries = Vec::new();
+        for (height, transaction) in mined.chain(pending) {
+            for account in &self.keystore.accounts {
+                let (direction, counterparty) = if transaction.sender == account.address {
+                    (Direction::Sent, &transaction.receiver)
+                } else if transaction.receiver != account.address {
+                    continue;
+                } else if transaction.sender == ROOT_ADDRESS {
+                    (Direction::Mined, &transaction.sender)
+                } else {
+                    (Direction::Received, &transaction.sender)
+                };
+                entries.push(Entry {
+                    id: transaction.id(),
+                    height,
+                    account: account.name.clone(),
+                    direction,
+                    counterparty: self.name_of(counterparty),
+                    amount: transaction.amount,
+                    fee: transaction.fee,
+                });
+            }
+        }
+        entries
+    }
+
+    fn name_of(&self, address: &str) -> String {
+        match self
+            .keystore
+            .accounts
+            .iter()
+            .find(|account| account.address == address)
+        {
+            Some(account) => account.name.clone(),
+            None => address.to_string(),
+        }
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let data = serde_json::to_string_pretty(&self.keystore)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        storage::write_atomic(&self.path, data.as_bytes())
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+fn check(key: &[u8; 32]) -> String {
+    hex::encode(Sha256::digest(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Params;
+    use crate::env::{Env, FixedClock, QuietLogger};
+    use crate::pow;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    const PASSPHRASE: &str = "correct horse";
+
+    // A wallet file of its own for every test, removed once it is dropped.
+    struct TempWallet(PathBuf);
+
+    impl TempWallet {
+        fn new(name: &str) -> TempWallet {
+            let dir = std::env::temp_dir().join(format!(
+                "toy_blockchain_wallet_{}_{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            TempWallet(dir.join("wallet.json"))
+        }
+    }
+
+    impl Drop for TempWallet {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.0.parent().unwrap());
+        }
+    }
+
+    // Rewrites the first account of the wallet file.
+    fn tamper(path: &Path, tamper: impl FnOnce(&mut Account)) {
+        let data = fs::read_to_string(path).unwrap();
+        let mut keystore: Keystore = serde_json::from_str(&data).unwrap();
+        tamper(&mut keystore.accounts[0]);
+        fs::write(path, serde_json::to_string(&keystore).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn keys_survive_a_round_trip() {
+        let file = TempWallet::new("round_trip");
+        let rng = &mut StdRng::seed_from_u64(1);
+        let key = keys::generate_keypair(rng);
+        let mut wallet = Wallet::create(&file.0, PASSPHRASE, rng).unwrap();
+        wallet.add_account("alice", &key, rng).unwrap();
+        assert!(matches!(
+            Wallet::create(&file.0, PASSPHRASE, rng),
+            Err(WalletError::Exists)
+        ));
+        assert!(matches!(
+            wallet.add_account("alice", &keys::generate_keypair(rng), rng),
+            Err(WalletError::DuplicateAccount(_))
+        ));
+
+        let wallet = Wallet::open(&file.0, PASSPHRASE).unwrap();
+        assert_eq!(wallet.resolve("alice"), keys::address(&key));
+        assert_eq!(wallet.signing_key("alice").unwrap(), key);
+        assert!(matches!(
+            wallet.signing_key("bob"),
+            Err(WalletError::UnknownAccount(_))
+        ));
+        assert!(matches!(
+            Wallet::open(&file.0, "wrong horse"),
+            Err(WalletError::Passphrase)
+        ));
+    }
+
+    #[test]
+    fn tampered_keys_are_refused() {
+        let file = TempWallet::new("tampered");
+        let rng = &mut StdRng::seed_from_u64(2);
+        let mut wallet = Wallet::create(&file.0, PASSPHRASE, rng).unwrap();
+        wallet
+            .add_account("alice", &keys::generate_keypair(rng), rng)
+            .unwrap();
+        let other = keys::address(&keys::generate_keypair(rng));
+
+        tamper(&file.0, |account| {
+            let flipped = match account.secret.starts_with('0') {
+                true => "1",
+                false => "0",
+            };
+            account.secret.replace_range(..1, flipped);
+        });
+        let wallet = Wallet::open(&file.0, PASSPHRASE).unwrap();
+        assert!(matches!(
+            wallet.signing_key("alice"),
+            Err(WalletError::Invalid(_))
+        ));
+
+        let file = TempWallet::new("moved");
+        let mut wallet = Wallet::create(&file.0, PASSPHRASE, rng).unwrap();
+        wallet
+            .add_account("alice", &keys::generate_keypair(rng), rng)
+            .unwrap();
+        tamper(&file.0, |account| account.address = other);
+        // The address is authenticated with the key, so it can't be swapped
+        let wallet = Wallet::open(&file.0, PASSPHRASE).unwrap();
+        assert!(matches!(
+            wallet.signing_key("alice"),
+            Err(WalletError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn history_tells_directions_apart() {
+        let file = TempWallet::new("history");
+        let rng = &mut StdRng::seed_from_u64(3);
+        let (alice, bob) = (keys::generate_keypair(rng), keys::generate_keypair(rng));
+        let mut wallet = Wallet::create(&file.0, PASSPHRASE, rng).unwrap();
+        wallet.add_account("alice", &alice, rng).unwrap();
+        wallet.add_account("bob", &bob, rng).unwrap();
+
+        let env = Env {
+            clock: Arc::new(FixedClock::new(1_700_000_000_000, 1_000)),
+            logger: Arc::new(QuietLogger),
+        };
+        let bits = pow::bits_from_zero_bits(1).unwrap();
+        let reward = Amount::from_coins(100).unwrap();
+        let mut chain = Chain::new(keys::address(&alice), reward, bits, Params::default(), env);
+        chain.miner_threads = 1;
+
+        let coins = |coins| Amount::from_coins(coins).unwrap();
+        let version = chain.params.version;
+        let paid = wallet
+            .transfer(rng, "alice", "bob", coins(10), coins(1), version)
+            .unwrap();
+        chain.new_transaction(paid.clone()).unwrap();
+        assert!(chain.generate_new_block());
+        let carol = keys::address(&keys::generate_keypair(rng));
+        let pending = wallet
+            .transfer(rng, "alice", &carol, coins(5), Amount::ZERO, version)
+            .unwrap();
+        chain.new_transaction(pending.clone()).unwrap();
+
+        let history: Vec<_> = wallet
+            .history(&chain)
+            .into_iter()
+            .map(|entry| {
+                (
+                    entry.height,
+                    entry.account,
+                    entry.direction,
+                    entry.counterparty,
+                    entry.id,
+                )
+            })
+            .collect();
+        let coinbase = |height: usize| chain.chain[height].transactions[0].id();
+        let entry = |height, account: &str, direction, counterparty: &str, id| {
+            (
+                height,
+                account.to_string(),
+                direction,
+                counterparty.to_string(),
+                id,
+            )
+        };
+        assert_eq!(
+            history,
+            [
+                entry(
+                    Some(0),
+                    "alice",
+                    Direction::Mined,
+                    ROOT_ADDRESS,
+                    coinbase(0)
+                ),
+                entry(
+                    Some(1),
+                    "alice",
+                    Direction::Mined,
+                    ROOT_ADDRESS,
+                    coinbase(1)
+                ),
+                entry(Some(1), "alice", Direction::Sent, "bob", paid.id()),
+                entry(Some(1), "bob", Direction::Received, "alice", paid.id()),
+                entry(None, "alice", Direction::Sent, &carol, pending.id()),
+            ]
+        );
+    }
+}